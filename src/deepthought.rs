@@ -6,18 +6,26 @@ use crate::*;
 
 impl DeepThought {
     pub fn new(gguf_model: &str) -> Result<Self, easy_error::Error> {
+        DeepThought::new_with_params(gguf_model, &DeepThoughtModelParams::default())
+    }
+
+    pub fn new_with_params(
+        gguf_model: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<Self, easy_error::Error> {
         let backend = match DeepThoughtBackend::new() {
             Ok(backend) => backend,
             Err(err) => {
                 easy_error::bail!("BACKEND ERROR: {:?}", err);
             }
         };
-        let model = match backend.load_model(gguf_model, "You are the robot!") {
-            Ok(model) => model,
-            Err(err) => {
-                easy_error::bail!("MODEL ERROR: {:?}", err);
-            }
-        };
+        let model =
+            match backend.load_model_with_params(gguf_model, "You are the robot!", model_params) {
+                Ok(model) => model,
+                Err(err) => {
+                    easy_error::bail!("MODEL ERROR: {:?}", err);
+                }
+            };
         Ok(DeepThought {
             dbpath: ".".to_string(),
             backend: backend,
@@ -30,7 +38,19 @@ impl DeepThought {
     }
 
     pub fn embed_model(&mut self, gguf_model: &str) -> Result<(), easy_error::Error> {
-        let model = match self.backend.load_model(gguf_model, "You are the robot!") {
+        self.embed_model_with_params(gguf_model, &DeepThoughtModelParams::default())
+    }
+
+    pub fn embed_model_with_params(
+        &mut self,
+        gguf_model: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<(), easy_error::Error> {
+        let model = match self.backend.load_model_with_params(
+            gguf_model,
+            "You are the robot!",
+            model_params,
+        ) {
             Ok(model) => model,
             Err(err) => {
                 easy_error::bail!("EMBED MODEL ERROR: {:?}", err);
@@ -40,6 +60,21 @@ impl DeepThought {
         Ok(())
     }
 
+    pub fn info(&self) -> DeepThoughtModelInfo {
+        self.model.info()
+    }
+
+    pub fn info_value(&self) -> Value {
+        self.model.info().to_value()
+    }
+
+    pub fn embed_info(&self) -> Option<DeepThoughtModelInfo> {
+        match self.embed_model {
+            Some(ref embed_model) => Some(embed_model.info()),
+            None => None,
+        }
+    }
+
     pub fn chat(&mut self, prompt: &str) -> Result<String, easy_error::Error> {
         self.model.chat(prompt)
     }
@@ -48,6 +83,90 @@ impl DeepThought {
         self.model.ask(prompt)
     }
 
+    pub fn chat_stream(
+        &mut self,
+        prompt: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        self.model.chat_stream(prompt, callback)
+    }
+
+    pub fn ask_stream(
+        &mut self,
+        prompt: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        self.model.ask_stream(prompt, callback)
+    }
+
+    pub fn chat_with_params(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<String, easy_error::Error> {
+        self.model.chat_with_params(prompt, params)
+    }
+
+    pub fn ask_with_params(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<String, easy_error::Error> {
+        self.model.ask_with_params(prompt, params)
+    }
+
+    pub fn chat_completion(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtCompletion, easy_error::Error> {
+        self.model.chat_completion(prompt, params)
+    }
+
+    pub fn ask_completion(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtCompletion, easy_error::Error> {
+        self.model.ask_completion(prompt, params)
+    }
+
+    pub fn count_tokens(&self, text: &str) -> Result<usize, easy_error::Error> {
+        match self.model.count_tokens(text) {
+            Ok(count) => Ok(count),
+            Err(err) => easy_error::bail!("Error counting tokens: {:?}", err),
+        }
+    }
+
+    pub fn truncate_to_tokens(&self, text: &str, n: usize) -> Result<String, easy_error::Error> {
+        match self.model.truncate_to_tokens(text, n) {
+            Ok(text) => Ok(text),
+            Err(err) => easy_error::bail!("Error truncating text: {:?}", err),
+        }
+    }
+
+    pub fn check_prompt(&self, prompt: &str) -> Result<usize, easy_error::Error> {
+        match self.model.check_prompt(prompt) {
+            Ok(count) => Ok(count),
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    pub fn ask_json<T: serde::de::DeserializeOwned + DeepThoughtJsonSchema>(
+        &mut self,
+        prompt: &str,
+    ) -> Result<T, easy_error::Error> {
+        self.model.ask_json(prompt)
+    }
+
+    pub fn save_session(&mut self, path: &str) -> Result<(), easy_error::Error> {
+        self.model.save_session(path)
+    }
+
+    pub fn load_session(&mut self, path: &str) -> Result<(), easy_error::Error> {
+        self.model.load_session(path)
+    }
+
     pub fn embed(&mut self, prompt: &str) -> Result<Vec<Vec<f32>>, easy_error::Error> {
         match self.embed_model {
             Some(ref mut model) => match model.embed(&[prompt]) {
@@ -58,6 +177,16 @@ impl DeepThought {
         }
     }
 
+    pub fn embed_batch(&mut self, prompts: &[String]) -> Result<Vec<Vec<f32>>, easy_error::Error> {
+        match self.embed_model {
+            Some(ref mut model) => match model.embed_batch(prompts) {
+                Ok(embeddings) => Ok(embeddings),
+                Err(err) => easy_error::bail!("EMBED ERROR: {:?}", err),
+            },
+            None => easy_error::bail!("Embedding model not loaded"),
+        }
+    }
+
     pub fn c(&mut self, prompt: Value) -> Result<Value, easy_error::Error> {
         let prompt_str = match prompt.conv(STRING) {
             Ok(str_val) => match str_val.cast_string() {
@@ -85,21 +214,170 @@ impl DeepThought {
             Err(err) => easy_error::bail!("LLAMA.CPP error: {}", err),
         };
     }
-    pub fn add_document(&mut self, doc: &str) -> Result<(), easy_error::Error> {
+    //
+    // Returns the id the document is stored under, the store and its document
+    // manifest are written by sync() or add_document_with_sync
+    //
+    pub fn add_document(&mut self, doc: &str) -> Result<String, easy_error::Error> {
         let embedder = match &self.embed_model {
             Some(embed_model) => embed_model,
             None => bail!("Embedding model not set"),
         };
+        let doc_id = nanoid::nanoid!();
+        match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.add_document(&doc_id, doc, &embedder) {
+                Ok(_) => Ok(doc_id),
+                Err(err) => bail!("Error adding document: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn add_document_with_splitter(
+        &mut self,
+        doc: &str,
+        splitter: DeepThoughtSplitter,
+    ) -> Result<String, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let doc_id = nanoid::nanoid!();
         match self.vecstore {
             Some(ref mut vecstore) => {
-                match vecstore.add_document(&nanoid::nanoid!(), doc, &embedder) {
-                    Ok(_) => Ok(()),
+                match vecstore.add_document_with_splitter(&doc_id, doc, &embedder, splitter) {
+                    Ok(_) => Ok(doc_id),
                     Err(err) => bail!("Error adding document: {}", err),
                 }
             }
             None => bail!("Vector store not set"),
         }
     }
+    pub fn add_document_with_metadata(
+        &mut self,
+        doc: &str,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<String, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let doc_id = nanoid::nanoid!();
+        match self.vecstore {
+            Some(ref mut vecstore) => {
+                match vecstore.add_document_with_metadata(&doc_id, doc, &embedder, metadata) {
+                    Ok(_) => Ok(doc_id),
+                    Err(err) => bail!("Error adding document: {}", err),
+                }
+            }
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn add_html(
+        &mut self,
+        html: &str,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<String, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let doc_id = nanoid::nanoid!();
+        match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.add_html(&doc_id, html, &embedder, metadata) {
+                Ok(_) => Ok(doc_id),
+                Err(err) => bail!("Error adding HTML document: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn add_markdown(
+        &mut self,
+        markdown: &str,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<String, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let doc_id = nanoid::nanoid!();
+        match self.vecstore {
+            Some(ref mut vecstore) => {
+                match vecstore.add_markdown(&doc_id, markdown, &embedder, metadata) {
+                    Ok(_) => Ok(doc_id),
+                    Err(err) => bail!("Error adding Markdown document: {}", err),
+                }
+            }
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn ingest_path(
+        &mut self,
+        path: &str,
+        options: &DeepThoughtIngestOptions,
+    ) -> Result<Vec<DeepThoughtIngestResult>, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        match self.vecstore {
+            Some(ref mut vecstore) => vecstore.ingest_path(path, &embedder, options),
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn sync_sources(
+        &mut self,
+        dry_run: bool,
+    ) -> Result<DeepThoughtSyncReport, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        match self.vecstore {
+            Some(ref mut vecstore) => vecstore.sync_sources(&embedder, dry_run),
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn sync_sources_with_sync(
+        &mut self,
+        dry_run: bool,
+    ) -> Result<DeepThoughtSyncReport, easy_error::Error> {
+        let report = self.sync_sources(dry_run)?;
+        if !dry_run {
+            self.sync()?;
+        }
+        Ok(report)
+    }
+    pub fn get_document(&self, doc_id: &str) -> Result<String, easy_error::Error> {
+        match self.vecstore {
+            Some(ref vecstore) => vecstore.get_document(doc_id),
+            None => bail!("Vector store not set"),
+        }
+    }
+    //
+    // Like add_document, written by sync() or the _with_sync variant
+    //
+    pub fn delete_document(&mut self, doc_id: &str) -> Result<bool, easy_error::Error> {
+        match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.delete_document(doc_id) {
+                Ok(deleted) => Ok(deleted),
+                Err(err) => bail!("Error deleting document: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        }
+    }
+    pub fn replace_document(&mut self, doc_id: &str, doc: &str) -> Result<(), easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.replace_document(doc_id, doc, &embedder) {
+                Ok(_) => Ok(()),
+                Err(err) => bail!("Error replacing document: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        }
+    }
     pub fn add_string(&mut self, doc: &str) -> Result<(), easy_error::Error> {
         let embedder = match &self.embed_model {
             Some(embed_model) => embed_model,
@@ -115,6 +393,30 @@ impl DeepThought {
             None => bail!("Vector store not set"),
         }
     }
+    pub fn add_string_with_metadata(
+        &mut self,
+        doc: &str,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        match self.vecstore {
+            Some(ref mut vecstore) => {
+                match vecstore.add_string_with_metadata(
+                    &nanoid::nanoid!(),
+                    doc,
+                    &embedder,
+                    metadata,
+                ) {
+                    Ok(_) => Ok(()),
+                    Err(err) => bail!("Error adding string: {}", err),
+                }
+            }
+            None => bail!("Vector store not set"),
+        }
+    }
     pub fn add_value(&mut self, doc: Value) -> Result<(), easy_error::Error> {
         let embedder = match &self.embed_model {
             Some(embed_model) => embed_model,
@@ -153,8 +455,30 @@ impl DeepThought {
             None => bail!("Vector store not set"),
         }
     }
-    pub fn add_document_with_sync(&mut self, doc: &str) -> Result<(), easy_error::Error> {
+    pub fn add_document_with_sync(&mut self, doc: &str) -> Result<String, easy_error::Error> {
         match self.add_document(doc) {
+            Ok(doc_id) => match self.sync() {
+                Ok(_) => Ok(doc_id),
+                Err(err) => bail!("{}", err),
+            },
+            Err(err) => bail!("{}", err),
+        }
+    }
+    pub fn delete_document_with_sync(&mut self, doc_id: &str) -> Result<bool, easy_error::Error> {
+        match self.delete_document(doc_id) {
+            Ok(deleted) => match self.sync() {
+                Ok(_) => Ok(deleted),
+                Err(err) => bail!("{}", err),
+            },
+            Err(err) => bail!("{}", err),
+        }
+    }
+    pub fn replace_document_with_sync(
+        &mut self,
+        doc_id: &str,
+        doc: &str,
+    ) -> Result<(), easy_error::Error> {
+        match self.replace_document(doc_id, doc) {
             Ok(_) => match self.sync() {
                 Ok(_) => Ok(()),
                 Err(err) => bail!("{}", err),
@@ -228,6 +552,54 @@ impl DeepThought {
         };
         Ok(results)
     }
+    //
+    // Neighbors with the metadata stored on each chunk
+    //
+    pub fn query_vecstore_neighbors(
+        &mut self,
+        q: &str,
+    ) -> Result<Vec<VecStoreNeighbors>, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let query = format!("{} {}", self.embedding_query_prefix, q);
+        let vector = match embedder.embed(&[query]) {
+            Ok(vector) => vector,
+            Err(err) => bail!("Error embedding query: {:?}", err),
+        };
+        let results = match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.query_neighbors(vector[0].clone(), q) {
+                Ok(results) => results,
+                Err(err) => bail!("Error querying: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        };
+        Ok(results.into_iter().map(VecStoreNeighbors::from).collect())
+    }
+    pub fn query_vecstore_filtered(
+        &mut self,
+        q: &str,
+        filter: &DeepThoughtFilter,
+    ) -> Result<Vec<String>, easy_error::Error> {
+        let embedder = match &self.embed_model {
+            Some(embed_model) => embed_model,
+            None => bail!("Embedding model not set"),
+        };
+        let query = format!("{} {}", self.embedding_query_prefix, q);
+        let vector = match embedder.embed(&[query]) {
+            Ok(vector) => vector,
+            Err(err) => bail!("Error embedding query: {:?}", err),
+        };
+        let results = match self.vecstore {
+            Some(ref mut vecstore) => match vecstore.query_filtered(vector[0].clone(), q, filter) {
+                Ok(results) => results,
+                Err(err) => bail!("Error querying: {}", err),
+            },
+            None => bail!("Vector store not set"),
+        };
+        Ok(results)
+    }
     pub fn query_vecstore_templated(
         &mut self,
         q: &str,
@@ -1,14 +1,12 @@
 extern crate log;
 
+use crate::deepthought_embed::DEFAULT_EMBED_SEQUENCES;
 use crate::*;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use llama_cpp_2::{
-    llama_backend::LlamaBackend,
-    llama_supports_mlock,
-    model::{LlamaChatMessage, LlamaModel, params::LlamaModelParams},
-    send_logs_to_tracing,
+    llama_backend::LlamaBackend, llama_supports_mlock, model::LlamaModel, send_logs_to_tracing,
 };
 
 //
@@ -36,36 +34,173 @@ impl DeepThoughtBackend {
                 };
                 Arc::new(lb)
             };
+            static ref MODEL_REGISTRY: Arc<Mutex<HashMap<DeepThoughtModelKey, Arc<LlamaModel>>>> =
+                Arc::new(Mutex::new(HashMap::new()));
         }
 
         Ok(Self {
             backend: LLAMA_BACKEND.clone(),
+            models: MODEL_REGISTRY.clone(),
         })
     }
 
+    fn model_key(
+        model_path: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<DeepThoughtModelKey, Error> {
+        let path = std::fs::canonicalize(model_path)?;
+        Ok(DeepThoughtModelKey {
+            path: path.to_string_lossy().to_string(),
+            params: model_params.key(),
+        })
+    }
+
+    //
+    // Returns a handle to already loaded weights or loads them from the GGUF file.
+    // The lock is held while loading so concurrent callers never load twice.
+    //
+    pub fn shared_model(
+        &self,
+        model_path: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<Arc<LlamaModel>, Error> {
+        let key = DeepThoughtBackend::model_key(model_path, model_params)?;
+        let mut models = match self.models.lock() {
+            Ok(models) => models,
+            Err(err) => return Err(Error::InternalNativeError(err.to_string())),
+        };
+        match models.get(&key) {
+            Some(model) => {
+                log::debug!("Sharing loaded model {}", &key.path);
+                return Ok(model.clone());
+            }
+            None => {}
+        }
+        let llama_params = model_params.to_llama()?;
+        let model = Arc::new(LlamaModel::load_from_file(
+            &self.backend,
+            &key.path,
+            &llama_params,
+        )?);
+        models.insert(key, model.clone());
+        Ok(model)
+    }
+
+    //
+    // Number of handles to the model held outside of the registry
+    //
+    pub fn model_refcount(
+        &self,
+        model_path: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<usize, Error> {
+        let key = DeepThoughtBackend::model_key(model_path, model_params)?;
+        match self.models.lock() {
+            Ok(models) => Ok(match models.get(&key) {
+                Some(model) => Arc::strong_count(model) - 1,
+                None => 0,
+            }),
+            Err(err) => Err(Error::InternalNativeError(err.to_string())),
+        }
+    }
+
+    pub fn loaded_models(&self) -> Result<Vec<(DeepThoughtModelKey, usize)>, Error> {
+        match self.models.lock() {
+            Ok(models) => Ok(models
+                .iter()
+                .map(|(key, model)| (key.clone(), Arc::strong_count(model) - 1))
+                .collect()),
+            Err(err) => Err(Error::InternalNativeError(err.to_string())),
+        }
+    }
+
+    //
+    // Removes the model from the registry, the weights are freed once the
+    // last handle still held by a model is dropped
+    //
+    pub fn unload_model(
+        &self,
+        model_path: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<bool, Error> {
+        let key = DeepThoughtBackend::model_key(model_path, model_params)?;
+        let mut models = match self.models.lock() {
+            Ok(models) => models,
+            Err(err) => return Err(Error::InternalNativeError(err.to_string())),
+        };
+        match models.remove(&key) {
+            Some(model) => {
+                let refcount = Arc::strong_count(&model) - 1;
+                if refcount > 0 {
+                    log::debug!(
+                        "Model {} unloaded while {} handles are alive",
+                        &key.path,
+                        refcount
+                    );
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    //
+    // Drops every model nobody holds a handle to, returns how many were unloaded
+    //
+    pub fn unload_unused_models(&self) -> Result<usize, Error> {
+        let mut models = match self.models.lock() {
+            Ok(models) => models,
+            Err(err) => return Err(Error::InternalNativeError(err.to_string())),
+        };
+        let before = models.len();
+        models.retain(|_, model| Arc::strong_count(model) > 1);
+        Ok(before - models.len())
+    }
+
     pub fn load_model(
         &self,
         model_path: &str,
         system_prompt: &str,
     ) -> Result<DeepThoughtModel, Error> {
-        let model_params = LlamaModelParams::default();
-        let model = LlamaModel::load_from_file(&self.backend, model_path, &model_params)?;
+        self.load_model_with_params(
+            model_path,
+            system_prompt,
+            &DeepThoughtModelParams::default(),
+        )
+    }
+
+    pub fn load_model_with_params(
+        &self,
+        model_path: &str,
+        system_prompt: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<DeepThoughtModel, Error> {
+        let key = DeepThoughtBackend::model_key(model_path, model_params)?;
+        let model = self.shared_model(model_path, model_params)?;
         let chat_template = match model.chat_template(None) {
             Ok(template) => Some(template),
             Err(_) => None,
         };
 
         Ok(DeepThoughtModel {
+            id: nanoid::nanoid!(),
+            model_path: key.path,
             registry: self.clone(),
             batch_size: DEFAULT_BATCH_SIZE,
-            context_length: DEFAULT_CONTEXT_LENGTH,
+            context_length: DeepThoughtBackend::default_context_length(&model),
             model,
+            context_options: DeepThoughtContextParams::default(),
+            lora_adapters: Vec::new(),
             chat_template,
             system_prompt: system_prompt.to_string(),
-            messages: vec![LlamaChatMessage::new(
-                "system".to_string(),
-                system_prompt.to_string(),
-            )?],
+            sampling: SamplingParams::default(),
+            messages: vec![DeepThoughtMessage::system(system_prompt)],
+            token_budget: None,
+            compaction: None,
+            kv_cache: None,
+            embed_sequences: DEFAULT_EMBED_SEQUENCES,
+            summarizer: None,
+            embedder: Mutex::new(None),
         })
     }
 
@@ -74,23 +209,50 @@ impl DeepThoughtBackend {
         model_path: &str,
         system_prompt: &str,
     ) -> Result<DeepThoughtCtxModel, Error> {
-        let model_params = LlamaModelParams::default();
-        let model = LlamaModel::load_from_file(&self.backend, model_path, &model_params)?;
+        self.load_context_model_with_params(
+            model_path,
+            system_prompt,
+            &DeepThoughtModelParams::default(),
+        )
+    }
+
+    pub fn load_context_model_with_params(
+        &self,
+        model_path: &str,
+        system_prompt: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<DeepThoughtCtxModel, Error> {
+        let model = self.shared_model(model_path, model_params)?;
         let chat_template = match model.chat_template(None) {
             Ok(template) => Some(template),
             Err(_) => None,
         };
 
         Ok(DeepThoughtCtxModel {
+            id: nanoid::nanoid!(),
             registry: self.clone(),
             batch_size: DEFAULT_BATCH_SIZE,
-            context_length: DEFAULT_CONTEXT_LENGTH,
+            context_length: DeepThoughtBackend::default_context_length(&model),
             model,
+            context_options: DeepThoughtContextParams::default(),
+            lora_adapters: Vec::new(),
             chat_template,
             system_prompt: system_prompt.to_string(),
+            sampling: SamplingParams::default(),
+            summarizer: None,
         })
     }
 
+    //
+    // DEFAULT_CONTEXT_LENGTH, capped by the context the model was trained on
+    //
+    pub fn default_context_length(model: &LlamaModel) -> usize {
+        match model.n_ctx_train() as usize {
+            0 => DEFAULT_CONTEXT_LENGTH,
+            n_ctx_train => std::cmp::min(n_ctx_train, DEFAULT_CONTEXT_LENGTH),
+        }
+    }
+
     pub fn supports_mlock() -> bool {
         llama_supports_mlock()
     }
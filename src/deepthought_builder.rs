@@ -1,10 +1,13 @@
 extern crate log;
-use crate::{DeepThought, DeepThoughtBuilder, DeepThoughtVecStore};
+use crate::{
+    DeepThought, DeepThoughtBuilder, DeepThoughtContextParams, DeepThoughtModelParams,
+    DeepThoughtSplitter, DeepThoughtVecStore, SamplingParams,
+};
 use easy_error::bail;
 use grainfs::dir::create_dir_recursive;
 use grainfs::path::*;
 
-use crate::deepthought_backend::{DEFAULT_BATCH_SIZE, DEFAULT_CONTEXT_LENGTH};
+use crate::deepthought_backend::DEFAULT_BATCH_SIZE;
 use crate::deepthought_vector::{
     DEFAULT_ALPHA, DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_SIZE, DEFAULT_K, DEFAULT_MAX_SCORE,
 };
@@ -24,6 +27,12 @@ impl DeepThoughtBuilder {
             alpha: DEFAULT_ALPHA,
             k: DEFAULT_K,
             max_score: DEFAULT_MAX_SCORE,
+            splitter: None,
+            sampling: None,
+            model_params: None,
+            context_params: None,
+            lora_adapters: Vec::new(),
+            embedding_dimension: None,
         }
     }
 
@@ -72,6 +81,14 @@ impl DeepThoughtBuilder {
         self
     }
 
+    //
+    // With DeepThoughtSplitter::Tokens chunk_size and chunk_overlap are in tokens
+    //
+    pub fn splitter(mut self, splitter: DeepThoughtSplitter) -> Self {
+        self.splitter = Some(splitter);
+        self
+    }
+
     pub fn k(mut self, size: usize) -> Self {
         self.k = size;
         self
@@ -87,6 +104,40 @@ impl DeepThoughtBuilder {
         self
     }
 
+    pub fn sampling(mut self, params: SamplingParams) -> Self {
+        self.sampling = Some(params);
+        self
+    }
+
+    //
+    // Used for both the chat and the embedding model
+    //
+    pub fn model_params(mut self, params: DeepThoughtModelParams) -> Self {
+        self.model_params = Some(params);
+        self
+    }
+
+    pub fn context_params(mut self, params: DeepThoughtContextParams) -> Self {
+        self.context_params = Some(params);
+        self
+    }
+
+    //
+    // Attaches a GGUF LoRA adapter to the chat model, can be called several times
+    //
+    pub fn lora_adapter(mut self, path: String, scale: f32) -> Self {
+        self.lora_adapters.push((path, scale));
+        self
+    }
+
+    //
+    // Expected embedding size, checked against the embedding model on build
+    //
+    pub fn embedding_dimension(mut self, dimension: usize) -> Self {
+        self.embedding_dimension = Some(dimension);
+        self
+    }
+
     fn fix_the_path(path: String) -> Option<String> {
         match try_expand_vars(&path) {
             Some(expanded_path) => match normalize_path(&expanded_path) {
@@ -136,12 +187,16 @@ impl DeepThoughtBuilder {
             Some(chat_path) => chat_path,
             None => String::from("chat.gguf"),
         };
-        let mut model = match DeepThought::new(&chat_gguf) {
+        let model_params = match self.model_params {
+            Some(params) => params,
+            None => DeepThoughtModelParams::default(),
+        };
+        let mut model = match DeepThought::new_with_params(&chat_gguf, &model_params) {
             Ok(model) => model,
             Err(err) => bail!("ERROR creating chat model: {}", err),
         };
         match self.embed_model_gguf {
-            Some(path) => match model.embed_model(&path) {
+            Some(path) => match model.embed_model_with_params(&path, &model_params) {
                 Ok(_) => {}
                 Err(err) => bail!("ERROR creating embedding model: {}", err),
             },
@@ -149,10 +204,21 @@ impl DeepThoughtBuilder {
                 log::debug!("Embedding model not provided");
             }
         };
+        let chat_info = model.model.info();
         let context_len = match self.context_length {
-            Some(len) => len,
-            None => DEFAULT_CONTEXT_LENGTH,
+            Some(len) => {
+                chat_info.check_context_length(len)?;
+                len
+            }
+            None => model.model.context_length,
         };
+        match (self.embedding_dimension, &model.embed_model) {
+            (Some(dimension), Some(embed_model)) => {
+                embed_model.info().check_embedding_dimension(dimension)?
+            }
+            (Some(_), None) => bail!("Embedding dimension set without an embedding model"),
+            (None, _) => {}
+        }
         let batch_size = match self.batch_size {
             Some(size) => size,
             None => DEFAULT_BATCH_SIZE,
@@ -171,12 +237,36 @@ impl DeepThoughtBuilder {
         };
         vecstore.chunk_size = chunk_size;
         vecstore.chunk_overlap = chunk_overlap;
+        match self.splitter {
+            Some(splitter) => vecstore.splitter = splitter,
+            None => {}
+        }
         vecstore.max_score = self.max_score;
         vecstore.k = self.k;
         vecstore.alpha = self.alpha;
         vecstore.embedding_prefix = self.embedding_doc_prefix.clone();
         model.model.context_length = context_len;
         model.model.batch_size = batch_size;
+        match self.sampling {
+            Some(params) => model.model.sampling = params,
+            None => {}
+        }
+        match self.context_params {
+            Some(params) => {
+                match model.embed_model {
+                    Some(ref mut embed_model) => embed_model.context_options = params.clone(),
+                    None => {}
+                }
+                model.model.context_options = params;
+            }
+            None => {}
+        }
+        for (path, scale) in self.lora_adapters.iter() {
+            match model.model.add_lora_adapter(path, *scale) {
+                Ok(_) => {}
+                Err(err) => bail!("ERROR loading LoRA adapter {}: {:?}", path, err),
+            }
+        }
         model.vecstore = Some(vecstore);
         Ok(model)
     }
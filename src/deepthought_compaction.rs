@@ -0,0 +1,356 @@
+extern crate log;
+
+use easy_error::bail;
+
+use crate::*;
+
+pub const DEFAULT_COMPACTION_KEEP_LAST: usize = 4;
+pub const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+pub const DEFAULT_COMPACTION_PROMPT: &str = r#"You summarize conversations.
+Write a concise summary of the conversation you are given. Preserve every fact, name, number,
+decision and instruction stated in it, so the conversation can continue without the original messages.
+Output only the summary."#;
+
+impl DeepThoughtCompaction {
+    pub fn new(threshold: usize) -> Self {
+        DeepThoughtCompaction {
+            threshold,
+            keep_last: DEFAULT_COMPACTION_KEEP_LAST,
+            prompt: DEFAULT_COMPACTION_PROMPT.to_string(),
+            summarizer: None,
+        }
+    }
+
+    pub fn keep_last(mut self, keep_last: usize) -> Self {
+        self.keep_last = keep_last;
+        self
+    }
+
+    pub fn prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    pub fn summarizer(mut self, summarizer: DeepThoughtSummarizer) -> Self {
+        self.summarizer = Some(summarizer);
+        self
+    }
+
+    //
+    // Summary apply folded into the system prompt, if any
+    //
+    pub fn summary(msg: &DeepThoughtMessage) -> Option<&str> {
+        match msg.metadata.get("summary") {
+            Some(serde_json::Value::String(summary)) if msg.role == "system" => Some(summary),
+            _ => None,
+        }
+    }
+
+    fn summary_block(summary: &str) -> String {
+        format!("{}\n{}", SUMMARY_PREFIX, summary)
+    }
+
+    //
+    // Indices of the messages to fold into the summary: droppable messages
+    // outside of the last keep_last messages
+    //
+    pub fn select(&self, messages: &[DeepThoughtMessage]) -> Vec<usize> {
+        let boundary = messages.len().saturating_sub(self.keep_last);
+        messages[..boundary]
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.is_droppable())
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    //
+    // The previous summary goes first, so the new one keeps what it said
+    //
+    pub fn transcript(messages: &[DeepThoughtMessage], selected: &[usize]) -> String {
+        let mut res = String::new();
+        match messages.first().and_then(DeepThoughtCompaction::summary) {
+            Some(summary) => res.push_str(&format!(
+                "{}\n\n",
+                DeepThoughtCompaction::summary_block(summary)
+            )),
+            None => {}
+        }
+        for n in selected.iter() {
+            let msg = &messages[*n];
+            res.push_str(&format!("{}: {}\n\n", msg.role, msg.content.trim()));
+        }
+        res
+    }
+
+    //
+    // Removes the selected messages and puts the summary at the end of the
+    // system prompt, replacing the previous one. Many chat templates accept
+    // only a single system message, at the start of the conversation.
+    //
+    pub fn apply(messages: &mut Vec<DeepThoughtMessage>, selected: &[usize], summary: &str) {
+        if selected.is_empty() {
+            return;
+        }
+        for n in selected.iter().rev() {
+            messages.remove(*n);
+        }
+        if messages
+            .first()
+            .map(|msg| msg.role != "system")
+            .unwrap_or(true)
+        {
+            messages.insert(0, DeepThoughtMessage::system(""));
+        }
+        let prompt = &mut messages[0];
+        let base = match DeepThoughtCompaction::summary(prompt) {
+            Some(previous) => {
+                let block = DeepThoughtCompaction::summary_block(previous);
+                match prompt.content.strip_suffix(&block) {
+                    Some(base) => base.trim_end().to_string(),
+                    None => prompt.content.clone(),
+                }
+            }
+            None => prompt.content.clone(),
+        };
+        let summary = summary.trim();
+        let block = DeepThoughtCompaction::summary_block(summary);
+        prompt.content = if base.is_empty() {
+            block
+        } else {
+            format!("{}\n\n{}", base, block)
+        };
+        prompt
+            .metadata
+            .insert("summary".to_string(), serde_json::json!(summary));
+    }
+}
+
+impl DeepThoughtSummarizer {
+    pub fn route(name: &str) -> Self {
+        DeepThoughtSummarizer::Route(name.to_string())
+    }
+
+    pub fn model(path: &str) -> Self {
+        DeepThoughtSummarizer::model_with_params(path, &DeepThoughtModelParams::default())
+    }
+
+    //
+    // Params equal to those of the conversation model share its weights
+    //
+    pub fn model_with_params(path: &str, params: &DeepThoughtModelParams) -> Self {
+        DeepThoughtSummarizer::Model {
+            path: path.to_string(),
+            params: params.clone(),
+        }
+    }
+}
+
+//
+// Summarizer model kept in loaded, loaded again only when the compaction
+// names another one. None when the conversation model summarizes itself.
+//
+fn loaded_summarizer<'a>(
+    registry: &DeepThoughtBackend,
+    loaded: &'a mut Option<(DeepThoughtSummarizer, Box<DeepThoughtModel>)>,
+    compaction: &DeepThoughtCompaction,
+) -> Result<Option<&'a mut DeepThoughtModel>, easy_error::Error> {
+    let summarizer = match compaction.summarizer {
+        Some(ref summarizer) => summarizer,
+        None => return Ok(None),
+    };
+    let (path, params) = match summarizer {
+        DeepThoughtSummarizer::Model { path, params } => (path, params),
+        DeepThoughtSummarizer::Route(_) => return Ok(None),
+    };
+    match *loaded {
+        Some((ref current, _)) if current == summarizer => {}
+        _ => {
+            let model = match registry.load_model_with_params(path, &compaction.prompt, params) {
+                Ok(model) => model,
+                Err(err) => bail!("Error loading summarizer {}: {:?}", path, err),
+            };
+            *loaded = Some((summarizer.clone(), Box::new(model)));
+        }
+    }
+    Ok(loaded.as_mut().map(|(_, model)| model.as_mut()))
+}
+
+impl DeepThoughtModel {
+    pub fn set_compaction(&mut self, compaction: Option<DeepThoughtCompaction>) {
+        self.compaction = compaction;
+    }
+
+    //
+    // One-shot inference on the transcript, the model history and its KV cache
+    // are left untouched. A model without a KV cache keeps the one created here,
+    // so a dedicated summarizer reuses its context.
+    //
+    pub fn summarize(
+        &mut self,
+        prompt: &str,
+        transcript: &str,
+    ) -> Result<String, easy_error::Error> {
+        let history =
+            std::mem::replace(&mut self.messages, vec![DeepThoughtMessage::system(prompt)]);
+        let compaction = self.compaction.take();
+        let token_budget = self.token_budget.take();
+        let kv_cache = self.kv_cache.take();
+        let params = self.sampling.clone();
+        let res = self.ask_with_params(transcript, &params);
+        self.messages = history;
+        self.compaction = compaction;
+        self.token_budget = token_budget;
+        match kv_cache {
+            Some(kv_cache) => self.kv_cache = Some(kv_cache),
+            None => {}
+        }
+        res
+    }
+
+    fn compaction_candidates(
+        &self,
+    ) -> Result<Option<(DeepThoughtCompaction, Vec<usize>)>, easy_error::Error> {
+        let compaction = match self.compaction {
+            Some(ref compaction) => compaction.clone(),
+            None => return Ok(None),
+        };
+        let tokens = match self.count_chat_tokens(&self.messages) {
+            Ok(tokens) => tokens,
+            Err(err) => bail!("Error counting history tokens: {:?}", err),
+        };
+        if tokens <= compaction.threshold {
+            return Ok(None);
+        }
+        let selected = compaction.select(&self.messages);
+        if selected.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((compaction, selected)))
+    }
+
+    //
+    // Summarizes older turns with the compaction summarizer or this model,
+    // returns true if history was compacted
+    //
+    pub fn compact_history(&mut self) -> Result<bool, easy_error::Error> {
+        let (compaction, selected) = match self.compaction_candidates()? {
+            Some(candidates) => candidates,
+            None => return Ok(false),
+        };
+        let transcript = DeepThoughtCompaction::transcript(&self.messages, &selected);
+        let summary = match loaded_summarizer(&self.registry, &mut self.summarizer, &compaction)? {
+            Some(summarizer) => summarizer.summarize(&compaction.prompt, &transcript)?,
+            None => self.summarize(&compaction.prompt, &transcript)?,
+        };
+        DeepThoughtCompaction::apply(&mut self.messages, &selected, &summary);
+        Ok(true)
+    }
+
+    //
+    // Summarizes older turns with another model
+    //
+    pub fn compact_history_with(
+        &mut self,
+        summarizer: &mut DeepThoughtModel,
+    ) -> Result<bool, easy_error::Error> {
+        let (compaction, selected) = match self.compaction_candidates()? {
+            Some(candidates) => candidates,
+            None => return Ok(false),
+        };
+        let transcript = DeepThoughtCompaction::transcript(&self.messages, &selected);
+        let summary = summarizer.summarize(&compaction.prompt, &transcript)?;
+        DeepThoughtCompaction::apply(&mut self.messages, &selected, &summary);
+        Ok(true)
+    }
+}
+
+impl DeepThoughtCtxModel {
+    pub fn summarize(
+        &mut self,
+        prompt: &str,
+        transcript: &str,
+    ) -> Result<String, easy_error::Error> {
+        let mut ctx = DeepThoughtContext::init(prompt)?;
+        self.chat(transcript, &mut ctx)
+    }
+}
+
+impl DeepThoughtCtxModel {
+    //
+    // Summarizes older turns of ctx with the compaction summarizer model or
+    // this model, returns true if history was compacted
+    //
+    pub fn compact_history(
+        &mut self,
+        ctx: &mut DeepThoughtContext,
+    ) -> Result<bool, easy_error::Error> {
+        let (compaction, selected) = match ctx.compaction_candidates(self)? {
+            Some(candidates) => candidates,
+            None => return Ok(false),
+        };
+        match loaded_summarizer(&self.registry, &mut self.summarizer, &compaction)? {
+            Some(summarizer) => {
+                let transcript = DeepThoughtCompaction::transcript(&ctx.messages, &selected);
+                let summary = summarizer.summarize(&compaction.prompt, &transcript)?;
+                DeepThoughtCompaction::apply(&mut ctx.messages, &selected, &summary);
+                Ok(true)
+            }
+            None => ctx.compact_selected(self, &compaction, &selected),
+        }
+    }
+}
+
+impl DeepThoughtContext {
+    pub fn set_compaction(&mut self, compaction: Option<DeepThoughtCompaction>) {
+        self.compaction = compaction;
+    }
+
+    fn compaction_candidates(
+        &self,
+        counter: &DeepThoughtCtxModel,
+    ) -> Result<Option<(DeepThoughtCompaction, Vec<usize>)>, easy_error::Error> {
+        let compaction = match self.compaction {
+            Some(ref compaction) => compaction.clone(),
+            None => return Ok(None),
+        };
+        let tokens = match counter.count_chat_tokens(&self.messages) {
+            Ok(tokens) => tokens,
+            Err(err) => bail!("Error counting history tokens: {:?}", err),
+        };
+        if tokens <= compaction.threshold {
+            return Ok(None);
+        }
+        let selected = compaction.select(&self.messages);
+        if selected.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((compaction, selected)))
+    }
+
+    fn compact_selected(
+        &mut self,
+        summarizer: &mut DeepThoughtCtxModel,
+        compaction: &DeepThoughtCompaction,
+        selected: &[usize],
+    ) -> Result<bool, easy_error::Error> {
+        let transcript = DeepThoughtCompaction::transcript(&self.messages, selected);
+        let summary = summarizer.summarize(&compaction.prompt, &transcript)?;
+        DeepThoughtCompaction::apply(&mut self.messages, selected, &summary);
+        Ok(true)
+    }
+
+    //
+    // Summarizes older turns with the given model, returns true if history was compacted
+    //
+    pub fn compact(
+        &mut self,
+        summarizer: &mut DeepThoughtCtxModel,
+    ) -> Result<bool, easy_error::Error> {
+        let (compaction, selected) = match self.compaction_candidates(summarizer)? {
+            Some(candidates) => candidates,
+            None => return Ok(false),
+        };
+        self.compact_selected(summarizer, &compaction, &selected)
+    }
+}
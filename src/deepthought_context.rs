@@ -9,6 +9,9 @@ impl DeepThoughtContext {
         Ok(DeepThoughtContext {
             messages: Vec::new(),
             max_msg: None,
+            token_budget: None,
+            compaction: None,
+            kv_cache: None,
         })
     }
     pub fn init(system_prompt: &str) -> Result<Self, easy_error::Error> {
@@ -25,58 +28,141 @@ impl DeepThoughtContext {
     pub fn maximum_messages(&mut self, max_msg: usize) {
         self.max_msg = Some(max_msg);
     }
-    pub fn system(&mut self, system_prompt: &str) -> Result<(), easy_error::Error> {
+    pub fn token_budget(&mut self, budget: DeepThoughtTokenBudget) {
+        self.token_budget = Some(budget);
+    }
+    pub fn get_token_budget(&self) -> Option<DeepThoughtTokenBudget> {
+        self.token_budget
+    }
+    //
+    // Keeps at most max_msg messages by dropping the oldest droppable ones
+    //
+    fn make_room(&mut self) -> Result<(), easy_error::Error> {
         match self.max_msg {
             Some(max_msg) => {
-                if self.messages.len() > max_msg {
-                    bail!("Context is full")
+                while self.messages.len() >= max_msg {
+                    match self.messages.iter().position(|msg| msg.is_droppable()) {
+                        Some(n) => {
+                            self.messages.remove(n);
+                        }
+                        None => bail!("Context is full"),
+                    }
                 }
             }
             None => {}
         }
-        let msg = match LlamaChatMessage::new("system".to_string(), system_prompt.to_string()) {
-            Ok(msg) => msg,
-            Err(err) => bail!("{}", err),
-        };
-        self.messages.push(msg);
         Ok(())
     }
-    pub fn user(&mut self, prompt: &str) -> Result<(), easy_error::Error> {
-        match self.max_msg {
-            Some(max_msg) => {
-                if self.messages.len() > max_msg {
-                    bail!("Context is full")
-                }
-            }
-            None => {}
-        }
-        let msg = match LlamaChatMessage::new("user".to_string(), prompt.to_string()) {
-            Ok(msg) => msg,
+    pub fn push(&mut self, msg: DeepThoughtMessage) -> Result<(), easy_error::Error> {
+        match self.make_room() {
+            Ok(_) => {}
             Err(err) => bail!("{}", err),
-        };
+        }
         self.messages.push(msg);
         Ok(())
     }
+    pub fn system(&mut self, system_prompt: &str) -> Result<(), easy_error::Error> {
+        self.push(DeepThoughtMessage::system(system_prompt))
+    }
+    pub fn user(&mut self, prompt: &str) -> Result<(), easy_error::Error> {
+        self.push(DeepThoughtMessage::user(prompt))
+    }
     pub fn assistant(&mut self, prompt: &str) -> Result<(), easy_error::Error> {
-        match self.max_msg {
-            Some(max_msg) => {
-                if self.messages.len() > max_msg {
-                    bail!("Context is full")
-                }
-            }
-            None => {}
-        }
-        let msg = match LlamaChatMessage::new("assistant".to_string(), prompt.to_string()) {
-            Ok(msg) => msg,
-            Err(err) => bail!("{}", err),
-        };
-        self.messages.push(msg);
-        Ok(())
+        self.push(DeepThoughtMessage::assistant(prompt))
     }
-    pub fn messages(&self) -> &Vec<LlamaChatMessage> {
+    pub fn messages(&self) -> &Vec<DeepThoughtMessage> {
         &self.messages
     }
+    pub fn messages_mut(&mut self) -> &mut Vec<DeepThoughtMessage> {
+        &mut self.messages
+    }
+    pub fn pin_last(&mut self) {
+        match self.messages.last_mut() {
+            Some(msg) => msg.pinned = true,
+            None => {}
+        }
+    }
     pub fn remove_last(&mut self) {
         let _ = self.messages.pop();
     }
+    pub fn clear_kv_cache(&mut self) {
+        self.kv_cache = None;
+    }
+    //
+    // Timestamp of the latest message, 0 for an empty context
+    //
+    pub fn updated(&self) -> u64 {
+        self.messages
+            .iter()
+            .map(|msg| msg.timestamp)
+            .max()
+            .unwrap_or(0)
+    }
+    pub fn to_record(&self) -> DeepThoughtContextRecord {
+        DeepThoughtContextRecord {
+            max_msg: self.max_msg,
+            token_budget: self.token_budget,
+            compaction: self.compaction.clone(),
+            updated: self.updated(),
+            messages: self.messages.clone(),
+        }
+    }
+    pub fn from_record(record: DeepThoughtContextRecord) -> Self {
+        DeepThoughtContext {
+            max_msg: record.max_msg,
+            token_budget: record.token_budget,
+            compaction: record.compaction,
+            messages: record.messages,
+            kv_cache: None,
+        }
+    }
+    pub fn to_json(&self) -> Result<String, easy_error::Error> {
+        match serde_json::to_string_pretty(&self.to_record()) {
+            Ok(res) => Ok(res),
+            Err(err) => bail!("Error serializing context: {}", err),
+        }
+    }
+    pub fn from_json(data: &str) -> Result<Self, easy_error::Error> {
+        match serde_json::from_str::<DeepThoughtContextRecord>(data) {
+            Ok(record) => Ok(DeepThoughtContext::from_record(record)),
+            Err(err) => bail!("Error deserializing context: {}", err),
+        }
+    }
+    pub fn save(&self, path: &str) -> Result<(), easy_error::Error> {
+        let data = self.to_json()?;
+        match std::fs::write(path, data) {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("Error saving context to {}: {}", path, err),
+        }
+    }
+    pub fn load(path: &str) -> Result<Self, easy_error::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(data) => DeepThoughtContext::from_json(&data),
+            Err(err) => bail!("Error loading context from {}: {}", path, err),
+        }
+    }
+    //
+    // Messages in the OpenAI chat format: [{"role": ..., "content": ...}, ...]
+    //
+    pub fn to_openai(&self) -> serde_json::Value {
+        serde_json::Value::Array(self.messages.iter().map(|msg| msg.to_openai()).collect())
+    }
+    pub fn from_openai(value: &serde_json::Value) -> Result<Self, easy_error::Error> {
+        let messages = match value {
+            serde_json::Value::Array(messages) => messages,
+            serde_json::Value::Object(request) => match request.get("messages") {
+                Some(serde_json::Value::Array(messages)) => messages,
+                _ => bail!("OpenAI request without messages"),
+            },
+            _ => bail!("OpenAI messages must be an array"),
+        };
+        let mut dtc = DeepThoughtContext::new()?;
+        for msg in messages.iter() {
+            match DeepThoughtMessage::from_openai(msg) {
+                Ok(msg) => dtc.messages.push(msg),
+                Err(err) => bail!("{:?}", err),
+            }
+        }
+        Ok(dtc)
+    }
 }
@@ -4,13 +4,13 @@ use crate::*;
 
 use std::num::NonZeroU32;
 
+use serde::de::DeserializeOwned;
 use std::io::Write;
 
 use llama_cpp_2::{
     context::params::LlamaContextParams,
     llama_batch::LlamaBatch,
-    model::{AddBos, LlamaChatTemplate, Special},
-    sampling::LlamaSampler,
+    model::{AddBos, LlamaChatTemplate},
 };
 
 impl DeepThoughtCtxModel {
@@ -19,9 +19,20 @@ impl DeepThoughtCtxModel {
         prompt: &str,
         ctx: &mut DeepThoughtContext,
         output: &mut impl Write,
+    ) -> Result<(), Error> {
+        let params = self.sampling.clone();
+        self.send_with_history_and_params(prompt, ctx, output, &params)
+    }
+
+    pub fn send_with_history_and_params(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+        output: &mut impl Write,
+        params: &SamplingParams,
     ) -> Result<(), Error> {
         let mut inference = vec![];
-        self.infer(prompt, ctx, &mut inference)?;
+        self.infer(prompt, ctx, &mut inference, params)?;
         let inference = match String::from_utf8(inference) {
             Ok(inference) => inference,
             Err(err) => return Err(format!("{}", err).into()),
@@ -41,84 +52,122 @@ impl DeepThoughtCtxModel {
         Ok(())
     }
 
+    pub fn active_chat_template(&self) -> Result<LlamaChatTemplate, Error> {
+        match self.chat_template {
+            Some(ref template) => Ok(template.clone()),
+            None => match LlamaChatTemplate::new("chatml") {
+                Ok(template) => Ok(template),
+                Err(err) => Err(format!("{}", err).into()),
+            },
+        }
+    }
+
+    pub fn context_params(&self) -> LlamaContextParams {
+        self.context_options.apply(
+            LlamaContextParams::default()
+                .with_n_batch(self.batch_size as u32)
+                .with_n_ctx(NonZeroU32::new(self.context_length as u32)),
+        )
+    }
+
+    //
+    // Context kept from the previous turn of ctx, a new one when there is
+    // none or it was created for other adapters
+    //
+    fn take_kv_cache(&mut self, ctx: &mut DeepThoughtContext) -> Result<DeepThoughtKvCache, Error> {
+        let mut cache = match ctx.kv_cache.take() {
+            Some(cache) if cache.owner == self.id => cache,
+            _ => {
+                let context_params = self.context_params();
+                DeepThoughtKvCache::new(
+                    &self.id,
+                    &self.registry.backend,
+                    self.model.clone(),
+                    context_params,
+                    &mut self.lora_adapters,
+                )?
+            }
+        };
+        cache.sync_lora_scales(&mut self.lora_adapters)?;
+        Ok(cache)
+    }
+
     fn infer(
         &mut self,
         prompt: &str,
         ctx: &mut DeepThoughtContext,
         output: &mut impl Write,
-    ) -> Result<(), Error> {
-        let chat_template = match self.chat_template {
-            Some(ref template) => template.clone(),
-            None => match LlamaChatTemplate::new("chatml") {
-                Ok(template) => template,
-                Err(err) => return Err(format!("{}", err).into()),
-            },
-        };
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtFinishReason, Error> {
+        let chat_template = self.active_chat_template()?;
+        match self.compact_history(ctx) {
+            Ok(_) => {}
+            Err(err) => return Err(format!("Error compacting history: {}", err).into()),
+        }
         match ctx.user(prompt) {
             Ok(_) => {}
             Err(err) => return Err(format!("{}", err).into()),
         }
+        match ctx.token_budget {
+            Some(budget) => {
+                match budget.fit(&mut ctx.messages, self.context_length, |messages| {
+                    self.count_chat_tokens(messages)
+                }) {
+                    Ok(_) => {}
+                    Err(err) => {
+                        ctx.remove_last();
+                        return Err(err);
+                    }
+                }
+            }
+            None => {}
+        }
+        let messages = DeepThoughtMessage::to_llama_messages(ctx.messages())?;
         let prompt = self
             .model
-            .apply_chat_template(&chat_template, ctx.messages(), true)?;
+            .apply_chat_template(&chat_template, &messages, true)?;
         let tokens = self.model.str_to_token(&prompt, AddBos::Always)?;
 
-        let context_params = LlamaContextParams::default()
-            .with_n_batch(self.batch_size as u32)
-            .with_n_ctx(NonZeroU32::new(self.context_length as u32));
-        let mut context = self
-            .model
-            .new_context(&self.registry.backend, context_params)?;
+        let mut cache = match self.take_kv_cache(ctx) {
+            Ok(cache) => cache,
+            Err(err) => {
+                ctx.remove_last();
+                return Err(err);
+            }
+        };
 
         let n_len = self.context_length as i32;
-        let n_cxt = context.n_ctx() as i32;
-        let n_kv_req = tokens.len() as i32 + (n_len - tokens.len() as i32);
-        if n_kv_req > n_cxt {
-            return Err(
-                Error::InternalNativeError(
-                    "n_kv_req > n_ctx, the required kv cache size is not big enough either reduce n_len or increase n_ctx".to_string()));
+        let n_ctx = cache.n_ctx();
+        if tokens.len() >= n_ctx {
+            ctx.kv_cache = Some(cache);
+            ctx.remove_last();
+            return Err(Error::ContextSize {
+                maximum: n_ctx,
+                actual: tokens.len(),
+            });
         }
 
         let mut batch = LlamaBatch::new(self.batch_size, 1);
 
-        let last_index = tokens.len() - 1;
-        for (i, token) in tokens.into_iter().enumerate() {
-            batch.add(token, i as i32, &[0], i == last_index)?;
-        }
-        context.decode(&mut batch)?;
-
-        // Decode and sample tokens.
-        let mut n_cur = batch.n_tokens();
-        let mut sampler = LlamaSampler::chain_simple([
-            LlamaSampler::min_p(0.05, 1),
-            LlamaSampler::temp(0.8),
-            LlamaSampler::dist(1337),
-        ]);
-        while n_cur <= n_len {
-            let token = sampler.sample(&context, batch.n_tokens() - 1);
-            sampler.accept(token);
-
-            if self.model.is_eog_token(token) {
-                eprintln!();
-                break;
-            }
-
-            let output_bytes = self.model.token_to_bytes(token, Special::Tokenize)?;
-            output.write_all(&output_bytes)?;
-            output.flush()?;
-
-            batch.clear();
-            batch.add(token, n_cur, &[0], true)?;
-
-            n_cur += 1;
-
-            match context.decode(&mut batch) {
-                Ok(_) => {}
-                Err(_) => return Err(Error::InternalNativeError("Decoding error".to_string())),
-            };
-        }
+        let n_past = cache.trim(&tokens);
+        let mut tokens = tokens;
+        // Decode and sample tokens, a failed turn leaves the context in an
+        // unknown state so the cache is dropped
+        let finish_reason = cache.with_context(|context| {
+            DeepThoughtKvCache::decode_tokens(
+                context,
+                &mut batch,
+                &tokens,
+                n_past,
+                self.batch_size,
+            )?;
+            params.generate(&self.model, context, &mut batch, &mut tokens, n_len, output)
+        });
         ctx.remove_last();
-        Ok(())
+        let finish_reason = finish_reason?;
+        cache.tokens = tokens;
+        ctx.kv_cache = Some(cache);
+        Ok(finish_reason)
     }
 
     pub fn chat(
@@ -134,4 +183,93 @@ impl DeepThoughtCtxModel {
             Err(err) => easy_error::bail!("{:?}", err),
         }
     }
+
+    pub fn chat_with_params(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+        params: &SamplingParams,
+    ) -> Result<String, easy_error::Error> {
+        let mut output = vec![];
+        match self.send_with_history_and_params(prompt, ctx, &mut output, params) {
+            Ok(_) => {
+                return Ok(String::from_utf8_lossy(&output).to_string());
+            }
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    pub fn chat_stream(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        let params = self.sampling.clone();
+        let mut stream = DeepThoughtStream::new(callback);
+        match self.infer(prompt, ctx, &mut stream, &params) {
+            Ok(_) => {}
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+        let inference = stream.finish();
+        match ctx.user(prompt) {
+            Ok(_) => {}
+            Err(err) => easy_error::bail!("{}", err),
+        }
+        match ctx.assistant(&inference) {
+            Ok(_) => Ok(inference),
+            Err(err) => easy_error::bail!("{}", err),
+        }
+    }
+
+    pub fn chat_completion(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtCompletion, easy_error::Error> {
+        let mut output = vec![];
+        let finish_reason = match self.infer(prompt, ctx, &mut output, params) {
+            Ok(finish_reason) => finish_reason,
+            Err(err) => easy_error::bail!("{:?}", err),
+        };
+        let text = String::from_utf8_lossy(&output).to_string();
+        match ctx.user(prompt) {
+            Ok(_) => {}
+            Err(err) => easy_error::bail!("{}", err),
+        }
+        match ctx.assistant(&text) {
+            Ok(_) => Ok(DeepThoughtCompletion {
+                text,
+                finish_reason,
+            }),
+            Err(err) => easy_error::bail!("{}", err),
+        }
+    }
+
+    //
+    // Chats with the output constrained by a grammar built from the JSON Schema
+    //
+    pub fn chat_json_schema<T: DeserializeOwned>(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+        schema: &serde_json::Value,
+    ) -> Result<T, easy_error::Error> {
+        let grammar = DeepThoughtGrammar::from_json_schema(schema)?;
+        let params = self.sampling.clone().grammar(&grammar);
+        let result = self.chat_with_params(prompt, ctx, &params)?;
+        match serde_json::from_str(&result) {
+            Ok(value) => Ok(value),
+            Err(err) => easy_error::bail!("Error parsing JSON output: {}", err),
+        }
+    }
+
+    pub fn chat_json<T: DeserializeOwned + DeepThoughtJsonSchema>(
+        &mut self,
+        prompt: &str,
+        ctx: &mut DeepThoughtContext,
+    ) -> Result<T, easy_error::Error> {
+        self.chat_json_schema(prompt, ctx, &T::json_schema())
+    }
 }
@@ -0,0 +1,331 @@
+extern crate log;
+
+use easy_error::bail;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use crate::*;
+use vecstore::Metadata;
+
+//
+// Manifest of documents and their chunk ids, kept in the vector store directory.
+// Like the vectors it is only written by save_vectorstore, so on disk the two
+// always describe the same chunks.
+//
+pub const DOCUMENT_MANIFEST: &str = "documents.json";
+
+//
+// Shorter matches between chunk ends and starts are taken for coincidence
+//
+const MIN_JOIN_OVERLAP: usize = 16;
+
+//
+// Joins chunks of a document back together, the part of a chunk repeating
+// the end of the previous one because of chunk overlap is dropped. Only a
+// guess, used for documents whose text is not in the manifest.
+//
+pub fn join_chunks(chunks: &[String]) -> String {
+    let mut res = String::new();
+    for chunk in chunks.iter() {
+        if res.is_empty() {
+            res.push_str(chunk);
+            continue;
+        }
+        let overlap = chunk
+            .char_indices()
+            .map(|(n, c)| n + c.len_utf8())
+            .filter(|n| *n >= MIN_JOIN_OVERLAP && res.ends_with(&chunk[..*n]))
+            .last();
+        match overlap {
+            Some(n) => res.push_str(&chunk[n..]),
+            None => {
+                res.push('\n');
+                res.push_str(chunk);
+            }
+        }
+    }
+    res
+}
+
+//
+// Missing manifest reads as empty
+//
+pub(crate) fn read_manifest<T: DeserializeOwned + Default>(
+    path: &Path,
+) -> Result<T, easy_error::Error> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => bail!("Error reading {}: {}", path.display(), err),
+    };
+    match serde_json::from_slice(&data) {
+        Ok(manifest) => Ok(manifest),
+        Err(err) => bail!("Error parsing {}: {}", path.display(), err),
+    }
+}
+
+//
+// Manifests are written to a temporary file first so a crash never leaves
+// a truncated one
+//
+pub(crate) fn write_manifest(path: &Path, data: &[u8]) -> Result<(), easy_error::Error> {
+    let tmp_path = path.with_extension("tmp");
+    match std::fs::write(&tmp_path, data) {
+        Ok(_) => {}
+        Err(err) => bail!("Error writing {}: {}", tmp_path.display(), err),
+    }
+    match std::fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(err) => bail!("Error renaming {}: {}", tmp_path.display(), err),
+    }
+}
+
+fn chunk_id(doc_id: &str, n: usize) -> String {
+    format!("{}-{}", doc_id, n)
+}
+
+impl DeepThoughtDocument {
+    pub fn new(
+        id: &str,
+        chunks: Vec<String>,
+        text: &str,
+        metadata: HashMap<String, serde_json::Value>,
+    ) -> Self {
+        DeepThoughtDocument {
+            id: id.to_string(),
+            chunks,
+            updated: DeepThoughtMessage::now(),
+            text: Some(text.to_string()),
+            metadata,
+        }
+    }
+}
+
+impl DeepThoughtVecStore {
+    fn manifest_path(path: &str) -> PathBuf {
+        PathBuf::from(path).join(DOCUMENT_MANIFEST)
+    }
+
+    pub(crate) fn load_documents(
+        path: &str,
+    ) -> Result<HashMap<String, DeepThoughtDocument>, easy_error::Error> {
+        read_manifest(&DeepThoughtVecStore::manifest_path(path))
+    }
+
+    pub fn save_documents(&self) -> Result<(), easy_error::Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let data = match serde_json::to_vec_pretty(&self.documents) {
+            Ok(data) => data,
+            Err(err) => bail!("Error serializing document manifest: {}", err),
+        };
+        write_manifest(&DeepThoughtVecStore::manifest_path(path), &data)
+    }
+
+    pub fn list_documents(&self) -> Vec<String> {
+        let mut res: Vec<String> = self.documents.keys().cloned().collect();
+        res.sort();
+        res
+    }
+
+    pub fn has_document(&self, doc_id: &str) -> bool {
+        !self.chunk_ids(doc_id).is_empty()
+    }
+
+    //
+    // Ids of soft deleted records, their text stays in the text index
+    //
+    fn deleted_ids(&self) -> HashSet<String> {
+        match self.conn.read() {
+            Ok(conn) => conn.list_deleted().into_iter().map(|r| r.id).collect(),
+            Err(err) => {
+                log::debug!("Failed to acquire read lock: {}", err);
+                HashSet::new()
+            }
+        }
+    }
+
+    //
+    // Chunk ids from the manifest, documents added before the manifest
+    // existed are found by probing {doc_id}-{n} for chunks not deleted
+    //
+    pub fn chunk_ids(&self, doc_id: &str) -> Vec<String> {
+        match self.documents.get(doc_id) {
+            Some(document) => document.chunks.clone(),
+            None => {
+                let deleted = self.deleted_ids();
+                let mut res: Vec<String> = Vec::new();
+                loop {
+                    let c_id = chunk_id(doc_id, res.len());
+                    if deleted.contains(&c_id) || self.get(&c_id).is_err() {
+                        break;
+                    }
+                    res.push(c_id);
+                }
+                res
+            }
+        }
+    }
+
+    pub(crate) fn embed_chunks(
+        &self,
+        text: &str,
+        embedder: &DeepThoughtModel,
+        splitter: DeepThoughtSplitter,
+    ) -> Result<(Vec<DeepThoughtChunk>, Vec<Vec<f32>>), easy_error::Error> {
+        let chunks: Vec<DeepThoughtChunk> = self.split_document(text, splitter, embedder)?;
+        let vectors = self.embed_texts(&chunks, embedder)?;
+        Ok((chunks, vectors))
+    }
+
+    pub(crate) fn embed_texts(
+        &self,
+        chunks: &[DeepThoughtChunk],
+        embedder: &DeepThoughtModel,
+    ) -> Result<Vec<Vec<f32>>, easy_error::Error> {
+        let texts: Vec<String> = chunks
+            .iter()
+            .map(|c| format!("{} {}", self.embedding_prefix, c.text))
+            .collect();
+        match embedder.embed_batch(&texts) {
+            Ok(vectors) => Ok(vectors),
+            Err(err) => bail!("Failed to embed text: {:?}", err),
+        }
+    }
+
+    //
+    // Writes all chunks of a document under one write lock, chunks of the
+    // previous version that are not overwritten are deleted. The text the
+    // chunks were split from goes into the manifest.
+    //
+    pub(crate) fn write_chunks(
+        &mut self,
+        doc_id: &str,
+        text: &str,
+        chunks: &[DeepThoughtChunk],
+        vectors: &[Vec<f32>],
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), easy_error::Error> {
+        let previous = self.chunk_ids(doc_id);
+        let vectors_store = self.conn.clone();
+        let mut conn = match vectors_store.write() {
+            Ok(conn) => conn,
+            Err(err) => bail!("Failed to acquire write lock: {}", err),
+        };
+        let mut ids: Vec<String> = Vec::new();
+        for (n, (c, vector)) in chunks.iter().zip(vectors.iter()).enumerate() {
+            let c_id = chunk_id(doc_id, n);
+            // Chunk metadata like heading_path is more specific than the document's
+            let mut meta = Metadata {
+                fields: metadata.clone(),
+            };
+            meta.fields
+                .extend(c.metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+            meta.fields.insert("id".into(), serde_json::json!(c_id));
+            meta.fields
+                .insert("doc_id".into(), serde_json::json!(doc_id));
+            meta.fields.insert("n".into(), serde_json::json!(n));
+            meta.fields.insert("text".into(), serde_json::json!(c.text));
+            match conn.upsert(c_id.clone(), vector.to_vec(), meta) {
+                Ok(_) => {}
+                Err(err) => bail!("Failed to add document: {}", err),
+            };
+            match conn.index_text(c_id.clone(), &c.text) {
+                Ok(_) => {}
+                Err(err) => bail!("Failed to index document: {}", err),
+            };
+            ids.push(c_id);
+        }
+        for c_id in previous.iter() {
+            if ids.contains(c_id) {
+                continue;
+            }
+            match conn.soft_delete(c_id) {
+                Ok(_) => {}
+                Err(err) => bail!("Failed to delete chunk {}: {}", c_id, err),
+            }
+        }
+        drop(conn);
+        drop(vectors_store);
+        self.documents.insert(
+            doc_id.to_string(),
+            DeepThoughtDocument::new(doc_id, ids, text, metadata.clone()),
+        );
+        Ok(())
+    }
+
+    //
+    // Deletes every chunk of the document, false when the document is unknown.
+    // Nothing is written until save_vectorstore.
+    //
+    pub fn delete_document(&mut self, doc_id: &str) -> Result<bool, easy_error::Error> {
+        let ids = self.chunk_ids(doc_id);
+        if ids.is_empty() {
+            return Ok(false);
+        }
+        let vectors = self.conn.clone();
+        let mut conn = match vectors.write() {
+            Ok(conn) => conn,
+            Err(err) => bail!("Failed to acquire write lock: {}", err),
+        };
+        for c_id in ids.iter() {
+            match conn.soft_delete(c_id) {
+                Ok(_) => {}
+                Err(err) => bail!("Failed to delete chunk {}: {}", c_id, err),
+            }
+        }
+        drop(conn);
+        drop(vectors);
+        self.documents.remove(doc_id);
+        Ok(true)
+    }
+
+    //
+    // Text of the document as it was added, reassembled from its chunks when
+    // the manifest does not have it
+    //
+    pub fn get_document(&self, doc_id: &str) -> Result<String, easy_error::Error> {
+        match self.documents.get(doc_id).and_then(|d| d.text.clone()) {
+            Some(text) => return Ok(text),
+            None => {}
+        }
+        let ids = self.chunk_ids(doc_id);
+        if ids.is_empty() {
+            bail!("No document found for id: {}", doc_id);
+        }
+        let mut chunks: Vec<String> = Vec::new();
+        for c_id in ids.iter() {
+            chunks.push(self.get(c_id)?);
+        }
+        Ok(join_chunks(&chunks))
+    }
+
+    //
+    // New chunks are embedded before anything is written, so a failure
+    // leaves the previous version of the document in place. Metadata the
+    // document was added with is kept. Nothing is written until save_vectorstore.
+    //
+    pub fn replace_document(
+        &mut self,
+        doc_id: &str,
+        text: &str,
+        embedder: &DeepThoughtModel,
+    ) -> Result<Duration, easy_error::Error> {
+        if !self.has_document(doc_id) {
+            bail!("No document found for id: {}", doc_id);
+        }
+        let metadata = match self.documents.get(doc_id) {
+            Some(document) => document.metadata.clone(),
+            None => HashMap::new(),
+        };
+        let splitter = self.splitter;
+        self.add_document_with(doc_id, text, embedder, splitter, &metadata)
+    }
+}
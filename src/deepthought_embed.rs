@@ -0,0 +1,200 @@
+extern crate log;
+
+use crate::*;
+
+use std::num::{NonZero, NonZeroU32};
+
+use llama_cpp_2::{context::params::LlamaContextParams, llama_batch::LlamaBatch, model::AddBos};
+
+//
+// Sequences decoded together in one embedding batch
+//
+pub const DEFAULT_EMBED_SEQUENCES: usize = 16;
+
+//
+// Groups consecutive sequences so that a group holds at most max_seqs
+// sequences and max_tokens tokens, a longer sequence gets a group of its own
+//
+pub fn embed_batches(lengths: &[usize], max_tokens: usize, max_seqs: usize) -> Vec<(usize, usize)> {
+    let mut res: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+    for (n, length) in lengths.iter().enumerate() {
+        if n > start && (n - start >= max_seqs || tokens + length > max_tokens) {
+            res.push((start, n));
+            start = n;
+            tokens = 0;
+        }
+        tokens += length;
+    }
+    if start < lengths.len() {
+        res.push((start, lengths.len()));
+    }
+    res
+}
+
+//
+// KV cells each sequence gets, the context is split evenly between sequences
+//
+pub fn embed_sequence_length(n_ctx: usize, sequences: usize) -> usize {
+    n_ctx / std::cmp::max(1, sequences)
+}
+
+fn normalize(embedding: &[f32]) -> Vec<f32> {
+    let embedding_magnitude = embedding
+        .iter()
+        .fold(0.0, |acc, &val| val.mul_add(val, acc))
+        .sqrt();
+    embedding
+        .iter()
+        .map(|&val| val / embedding_magnitude)
+        .collect()
+}
+
+impl DeepThoughtModel {
+    pub fn set_embed_sequences(&mut self, sequences: usize) {
+        self.embed_sequences = std::cmp::max(1, sequences);
+        match self.embedder.get_mut() {
+            Ok(embedder) => *embedder = None,
+            Err(err) => *err.into_inner() = None,
+        }
+    }
+
+    //
+    // Every sequence gets its own context_length cells of the KV cache
+    //
+    fn embed_context_params(&self, sequences: usize) -> LlamaContextParams {
+        let thread_count = std::thread::available_parallelism()
+            .unwrap_or(NonZero::new(1).unwrap())
+            .get() as i32;
+        self.context_options.apply(
+            LlamaContextParams::default()
+                .with_n_batch(self.context_length as u32)
+                .with_n_ubatch(self.context_length as u32)
+                .with_n_ctx(NonZeroU32::new((self.context_length * sequences) as u32))
+                .with_n_seq_max(sequences as u32)
+                .with_n_threads(thread_count)
+                .with_n_threads_batch(thread_count)
+                .with_embeddings(true),
+        )
+    }
+
+    //
+    // Creates an embedding context for up to sequences texts per decode,
+    // capped by embed_sequences
+    //
+    pub fn embedder(&self, sequences: usize) -> Result<DeepThoughtEmbedder, Error> {
+        let sequences = sequences.clamp(1, std::cmp::max(1, self.embed_sequences));
+        let params = self.embed_context_params(sequences);
+        let context = DeepThoughtLiveContext::try_new(self.model.clone(), |model| {
+            model.new_context(&self.registry.backend, params)
+        })?;
+        Ok(DeepThoughtEmbedder {
+            context,
+            context_length: self.context_length,
+            sequences,
+        })
+    }
+
+    //
+    // Sequences of the embedding context kept by the model, None before the
+    // first embed call
+    //
+    pub fn embedder_sequences(&self) -> Option<usize> {
+        match self.embedder.lock() {
+            Ok(embedder) => embedder.as_ref().map(|embedder| embedder.sequences()),
+            Err(_) => None,
+        }
+    }
+
+    pub fn embed(&self, text: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>, Error> {
+        self.embed_batch(text)
+    }
+
+    //
+    // A context holding fewer sequences than the texts need, or created for
+    // another context_length, is replaced by one sized for them, so a single
+    // query never allocates a batch context
+    //
+    pub fn embed_batch(&self, text: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut embedder = match self.embedder.lock() {
+            Ok(embedder) => embedder,
+            Err(err) => return Err(Error::InternalNativeError(err.to_string())),
+        };
+        let sequences = std::cmp::min(text.len(), self.embed_sequences);
+        match *embedder {
+            Some(ref current)
+                if current.sequences >= sequences
+                    && current.context_length == self.context_length => {}
+            _ => *embedder = Some(self.embedder(sequences)?),
+        }
+        match *embedder {
+            Some(ref mut embedder) => embedder.embed_batch(text),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl DeepThoughtEmbedder {
+    pub fn sequences(&self) -> usize {
+        self.sequences
+    }
+
+    pub fn n_ctx(&mut self) -> usize {
+        self.context
+            .with_dependent_mut(|_, context| context.n_ctx() as usize)
+    }
+
+    //
+    // Embeds many texts packing several sequences into every decode
+    //
+    pub fn embed_batch(&mut self, text: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>, Error> {
+        let sequences = self.sequences;
+        self.context
+            .with_dependent_mut(|model, context| -> Result<_, Error> {
+                // Tokenize the text.
+                let mut tokens = Vec::with_capacity(text.len());
+                for text in text {
+                    tokens.push(model.str_to_token(text.as_ref(), AddBos::Always)?);
+                }
+
+                // Make sure the KV cells of one sequence can hold every text.
+                let n_seq = embed_sequence_length(context.n_ctx() as usize, sequences);
+                let n_ubatch = context.n_ubatch() as usize;
+                for tokens in &tokens {
+                    if n_seq < tokens.len() {
+                        return Err(Error::ContextSize {
+                            maximum: n_seq,
+                            actual: tokens.len(),
+                        });
+                    } else if n_ubatch < tokens.len() {
+                        return Err(Error::MicrobatchSize {
+                            maximum: n_ubatch,
+                            actual: tokens.len(),
+                        });
+                    }
+                }
+
+                let lengths: Vec<usize> = tokens.iter().map(|tokens| tokens.len()).collect();
+                let mut batch = LlamaBatch::new(n_ubatch, sequences as i32);
+                let mut embeddings = Vec::with_capacity(tokens.len());
+                for (start, end) in embed_batches(&lengths, n_ubatch, sequences) {
+                    batch.clear();
+                    for (seq_id, tokens) in tokens[start..end].iter().enumerate() {
+                        batch.add_sequence(tokens, seq_id as i32, false)?;
+                    }
+
+                    // Run inference for embedding.
+                    context.clear_kv_cache();
+                    context.decode(&mut batch)?;
+
+                    // Extract embeddings of every sequence in the batch.
+                    for seq_id in 0..end - start {
+                        let embedding = context.embeddings_seq_ith(seq_id as i32)?;
+                        embeddings.push(normalize(embedding));
+                    }
+                }
+                Ok(embeddings)
+            })
+    }
+}
@@ -0,0 +1,551 @@
+extern crate log;
+
+use scraper::{ElementRef, Html, Node, Selector};
+use std::time::Duration;
+use took::Timer;
+
+use crate::deepthought_splitter::heading_anchor;
+use crate::*;
+
+//
+// Elements that carry no page content: scripts, styles, navigation and controls
+//
+const SKIPPED_ELEMENTS: [&str; 9] = [
+    "head", "script", "style", "noscript", "template", "nav", "svg", "iframe", "button",
+];
+
+//
+// Elements that start a new paragraph in the Markdown output
+//
+const BLOCK_ELEMENTS: [&str; 22] = [
+    "html",
+    "body",
+    "main",
+    "article",
+    "section",
+    "header",
+    "footer",
+    "aside",
+    "div",
+    "p",
+    "figure",
+    "figcaption",
+    "dl",
+    "dt",
+    "dd",
+    "address",
+    "details",
+    "summary",
+    "form",
+    "fieldset",
+    "caption",
+    "center",
+];
+
+#[derive(Default)]
+struct MarkdownWriter {
+    blocks: Vec<String>,
+    inline: String,
+}
+
+impl MarkdownWriter {
+    fn flush(&mut self) {
+        let text = collapse_whitespace(&self.inline);
+        if !text.is_empty() {
+            self.blocks.push(text);
+        }
+        self.inline.clear();
+    }
+
+    fn block(&mut self, text: String) {
+        self.flush();
+        if !text.trim().is_empty() {
+            self.blocks.push(text);
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn write_children(element: ElementRef, w: &mut MarkdownWriter) {
+    for child in element.children() {
+        match ElementRef::wrap(child) {
+            Some(child) => write_element(child, w),
+            None => match child.value() {
+                Node::Text(text) => w.inline.push_str(text),
+                _ => {}
+            },
+        }
+    }
+}
+
+fn write_element(element: ElementRef, w: &mut MarkdownWriter) {
+    let name = element.value().name();
+    if SKIPPED_ELEMENTS.contains(&name) {
+        return;
+    }
+    match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap_or(1);
+            let title = inline_text(element);
+            if title.is_empty() {
+                return;
+            }
+            let mut heading = format!("{} {}", "#".repeat(level), title);
+            match element.value().attr("id") {
+                Some(id) if !id.trim().is_empty() => {
+                    heading.push_str(&format!(" {{#{}}}", id.trim()))
+                }
+                _ => {}
+            }
+            w.block(heading);
+        }
+        "ul" | "ol" => w.block(list_markdown(element, 0)),
+        "table" => w.block(table_markdown(element)),
+        "pre" => {
+            let code: String = element.text().collect();
+            w.block(format!("```\n{}\n```", code.trim_matches('\n')));
+        }
+        "code" => {
+            let code: String = element.text().collect();
+            if !code.trim().is_empty() {
+                w.inline.push_str(&format!("`{}`", code.trim()));
+            }
+        }
+        "blockquote" => {
+            let mut inner = MarkdownWriter::default();
+            write_children(element, &mut inner);
+            inner.flush();
+            let quoted: Vec<String> = inner
+                .blocks
+                .join("\n\n")
+                .lines()
+                .map(|line| format!("> {}", line).trim_end().to_string())
+                .collect();
+            w.block(quoted.join("\n"));
+        }
+        "img" => match element.value().attr("alt") {
+            Some(alt) => {
+                w.inline.push(' ');
+                w.inline.push_str(alt);
+                w.inline.push(' ');
+            }
+            None => {}
+        },
+        "br" | "hr" => w.flush(),
+        name if BLOCK_ELEMENTS.contains(&name) => {
+            w.flush();
+            write_children(element, w);
+            w.flush();
+        }
+        _ => write_children(element, w),
+    }
+}
+
+fn inline_text(element: ElementRef) -> String {
+    let mut w = MarkdownWriter::default();
+    write_children(element, &mut w);
+    w.flush();
+    w.blocks.join(" ")
+}
+
+fn list_markdown(list: ElementRef, depth: usize) -> String {
+    let ordered = list.value().name() == "ol";
+    let indent = "  ".repeat(depth);
+    let mut lines: Vec<String> = Vec::new();
+    let mut n = 0;
+    for item in list.children().filter_map(ElementRef::wrap) {
+        if item.value().name() != "li" {
+            continue;
+        }
+        n += 1;
+        let marker = if ordered {
+            format!("{}.", n)
+        } else {
+            "-".to_string()
+        };
+        let mut w = MarkdownWriter::default();
+        let mut nested: Vec<String> = Vec::new();
+        for child in item.children() {
+            match ElementRef::wrap(child) {
+                Some(child) if matches!(child.value().name(), "ul" | "ol") => {
+                    nested.push(list_markdown(child, depth + 1));
+                }
+                Some(child) => write_element(child, &mut w),
+                None => match child.value() {
+                    Node::Text(text) => w.inline.push_str(text),
+                    _ => {}
+                },
+            }
+        }
+        w.flush();
+        lines.push(format!("{}{} {}", indent, marker, w.blocks.join(" ")));
+        lines.extend(nested.into_iter().filter(|list| !list.is_empty()));
+    }
+    lines.join("\n")
+}
+
+fn table_markdown(table: ElementRef) -> String {
+    let selector = match Selector::parse("tr") {
+        Ok(selector) => selector,
+        Err(_) => return inline_text(table),
+    };
+    let rows: Vec<Vec<String>> = table
+        .select(&selector)
+        .map(|row| {
+            row.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|cell| matches!(cell.value().name(), "th" | "td"))
+                .map(|cell| inline_text(cell).replace('|', "\\|"))
+                .collect::<Vec<String>>()
+        })
+        .filter(|row| !row.is_empty())
+        .collect();
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut lines: Vec<String> = Vec::new();
+    for (n, row) in rows.iter().enumerate() {
+        let mut cells = row.clone();
+        cells.resize(width, String::new());
+        lines.push(format!("| {} |", cells.join(" | ")));
+        // The first row is the header row of the Markdown table
+        if n == 0 {
+            lines.push(format!("|{}|", vec![" --- "; width].join("|")));
+        }
+    }
+    lines.join("\n")
+}
+
+//
+// Renders the content of an HTML page as Markdown: scripts, styles and
+// navigation are dropped, headings keep their id as {#anchor}, lists and
+// tables keep their structure
+//
+pub fn html_to_markdown(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut w = MarkdownWriter::default();
+    write_element(document.root_element(), &mut w);
+    w.flush();
+    w.blocks.join("\n\n")
+}
+
+//
+// Clean text of an HTML page
+//
+pub fn html_to_text(html: &str) -> String {
+    strip_markdown(&html_to_markdown(html))
+}
+
+fn html_title(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("title").ok()?;
+    let title = collapse_whitespace(
+        &document
+            .select(&selector)
+            .next()?
+            .text()
+            .collect::<String>(),
+    );
+    if title.is_empty() { None } else { Some(title) }
+}
+
+//
+// Title of a Markdown document is its first level 1 heading
+//
+pub fn markdown_title(markdown: &str) -> Option<String> {
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if !in_fence && trimmed.starts_with("# ") {
+            let (title, _) = heading_anchor(trimmed[2..].trim_end_matches('#'));
+            let title = strip_inline(&title);
+            if !title.is_empty() {
+                return Some(title);
+            }
+        }
+    }
+    None
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ['-', '*', '_', '=']
+            .iter()
+            .any(|mark| marks.iter().all(|c| c == mark))
+}
+
+fn is_table_separator(line: &str) -> bool {
+    line.contains('|')
+        && line.contains('-')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+//
+// Label and end position of [label](url) starting at the '[' at start
+//
+fn link_at(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut close = None;
+    for (i, c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = chars[close + 1..].iter().position(|c| *c == ')')? + close + 2;
+    Some((chars[start + 1..close].iter().collect(), end))
+}
+
+//
+// Emphasis markers next to words are dropped, "a * b" and snake_case are kept
+//
+fn is_emphasis(chars: &[char], i: usize) -> bool {
+    let before = i > 0 && !chars[i - 1].is_whitespace();
+    let after = i + 1 < chars.len() && !chars[i + 1].is_whitespace();
+    let word = |c: Option<&char>| c.map(|c| c.is_alphanumeric()).unwrap_or(false);
+    if chars[i] == '_'
+        && word(i.checked_sub(1).and_then(|i| chars.get(i)))
+        && word(chars.get(i + 1))
+    {
+        return false;
+    }
+    before || after
+}
+
+fn strip_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                res.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' => match chars[i + 1..].iter().position(|c| *c == '`') {
+                Some(len) => {
+                    res.extend(chars[i + 1..i + 1 + len].iter());
+                    i += len + 2;
+                    continue;
+                }
+                None => {
+                    i += 1;
+                    continue;
+                }
+            },
+            '!' | '[' => {
+                let start = if c == '!' { i + 1 } else { i };
+                if chars.get(start) == Some(&'[') {
+                    match link_at(&chars, start) {
+                        Some((label, end)) => {
+                            res.push_str(&strip_inline(&label));
+                            i = end;
+                            continue;
+                        }
+                        None => {}
+                    }
+                }
+            }
+            '~' if chars.get(i + 1) == Some(&'~') => {
+                i += 2;
+                continue;
+            }
+            '*' | '_' if is_emphasis(&chars, i) => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        res.push(c);
+        i += 1;
+    }
+    res
+}
+
+//
+// Drops Markdown syntax and keeps the structure: headings, list items and
+// table rows stay on their own lines, paragraphs are separated by a blank line
+//
+pub fn strip_markdown(markdown: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+        let indent = &line[..line.len() - trimmed.len()];
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        let text = if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            heading_anchor(trimmed[level..].trim().trim_end_matches('#')).0
+        } else if is_table_separator(trimmed) || (!trimmed.contains('|') && is_rule(trimmed)) {
+            continue;
+        } else if trimmed.starts_with('|') {
+            trimmed
+                .trim_end()
+                .trim_start_matches('|')
+                .trim_end_matches('|')
+                .trim()
+                .to_string()
+        } else if trimmed.starts_with('>') {
+            trimmed.trim_start_matches(['>', ' ']).to_string()
+        } else if trimmed.starts_with("* ") || trimmed.starts_with("+ ") {
+            format!("{}- {}", indent, &trimmed[2..])
+        } else {
+            line.to_string()
+        };
+        lines.push(strip_inline(&text).trim_end().to_string());
+    }
+    let mut res: Vec<String> = Vec::new();
+    for line in lines.into_iter() {
+        if line.is_empty() && res.last().map(|last| last.is_empty()).unwrap_or(true) {
+            continue;
+        }
+        res.push(line);
+    }
+    res.join("\n").trim().to_string()
+}
+
+impl DeepThoughtExtracted {
+    pub fn from_markdown(markdown: &str) -> Self {
+        DeepThoughtExtracted {
+            title: markdown_title(markdown),
+            markdown: markdown.to_string(),
+        }
+    }
+
+    pub fn from_html(html: &str) -> Self {
+        let markdown = html_to_markdown(html);
+        let title = match html_title(html) {
+            Some(title) => Some(title),
+            None => markdown_title(&markdown),
+        };
+        DeepThoughtExtracted { title, markdown }
+    }
+
+    //
+    // Splits by headings and strips the Markdown syntax of every chunk,
+    // heading_path and anchor of the section are kept as chunk metadata
+    //
+    pub fn chunks(&self, chunk_size: usize, chunk_overlap: usize) -> Vec<DeepThoughtChunk> {
+        let mut res: Vec<DeepThoughtChunk> = Vec::new();
+        for mut c in DeepThoughtSplitter::Markdown
+            .split(&self.markdown, chunk_size, chunk_overlap)
+            .into_iter()
+        {
+            c.text = strip_markdown(&c.text);
+            if !c.text.is_empty() {
+                res.push(c);
+            }
+        }
+        res
+    }
+}
+
+impl DeepThoughtVecStore {
+    pub fn add_markdown(
+        &mut self,
+        id: &str,
+        markdown: &str,
+        embedder: &DeepThoughtModel,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<Duration, easy_error::Error> {
+        self.add_extracted(
+            id,
+            &DeepThoughtExtracted::from_markdown(markdown),
+            embedder,
+            metadata,
+        )
+    }
+
+    pub fn add_html(
+        &mut self,
+        id: &str,
+        html: &str,
+        embedder: &DeepThoughtModel,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<Duration, easy_error::Error> {
+        self.add_extracted(
+            id,
+            &DeepThoughtExtracted::from_html(html),
+            embedder,
+            metadata,
+        )
+    }
+
+    //
+    // Whole sections split by the embedder tokenizer, every piece keeps the
+    // heading metadata of its section
+    //
+    fn split_extracted_tokens(
+        &self,
+        extracted: &DeepThoughtExtracted,
+        embedder: &DeepThoughtModel,
+    ) -> Result<Vec<DeepThoughtChunk>, easy_error::Error> {
+        let mut res: Vec<DeepThoughtChunk> = Vec::new();
+        for section in extracted.chunks(usize::MAX, 0).into_iter() {
+            for text in self.split_tokens(&section.text, embedder)?.into_iter() {
+                res.push(DeepThoughtChunk {
+                    text,
+                    metadata: section.metadata.clone(),
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    //
+    // Page title goes into the metadata of every chunk unless the caller set one
+    //
+    pub fn add_extracted(
+        &mut self,
+        id: &str,
+        extracted: &DeepThoughtExtracted,
+        embedder: &DeepThoughtModel,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<Duration, easy_error::Error> {
+        let timer = Timer::new();
+        let chunks = match self.splitter {
+            DeepThoughtSplitter::Tokens => self.split_extracted_tokens(extracted, embedder)?,
+            _ => extracted.chunks(self.chunk_size, self.chunk_overlap),
+        };
+        let mut metadata = metadata.clone();
+        match extracted.title {
+            Some(ref title) => {
+                metadata
+                    .entry("title".to_string())
+                    .or_insert(serde_json::json!(title));
+            }
+            None => {}
+        }
+        let vectors = self.embed_texts(&chunks, embedder)?;
+        self.write_chunks(id, &extracted.markdown, &chunks, &vectors, &metadata)?;
+        Ok(*timer.took().as_std())
+    }
+}
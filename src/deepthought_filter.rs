@@ -0,0 +1,240 @@
+extern crate log;
+
+use crate::*;
+
+//
+// Days since 1970-01-01 of a proleptic Gregorian date
+//
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn parse_number(text: &str, from: usize, len: usize) -> Option<i64> {
+    let part = text.get(from..from + len)?;
+    if !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse::<i64>().ok()
+}
+
+//
+// Unix seconds of an RFC 3339 timestamp or a YYYY-MM-DD date, numbers are
+// taken for unix seconds already
+//
+pub fn parse_timestamp(value: &serde_json::Value) -> Option<i64> {
+    let text = match value {
+        serde_json::Value::Number(number) => return number.as_f64().map(|n| n as i64),
+        serde_json::Value::String(text) => text.trim(),
+        _ => return None,
+    };
+    let year = parse_number(text, 0, 4)?;
+    let month = parse_number(text, 5, 2)?;
+    let day = parse_number(text, 8, 2)?;
+    if text.get(4..5)? != "-"
+        || text.get(7..8)? != "-"
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+    {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+    let rest = &text[10..];
+    if rest.is_empty() {
+        return Some(seconds);
+    }
+    if !rest.starts_with('T') && !rest.starts_with(' ') {
+        return None;
+    }
+    if rest.get(3..4)? != ":" {
+        return None;
+    }
+    seconds += parse_number(rest, 1, 2)? * 3600 + parse_number(rest, 4, 2)? * 60;
+    let mut rest = &rest[6..];
+    if rest.starts_with(':') {
+        seconds += parse_number(rest, 1, 2)?;
+        rest = &rest[3..];
+    }
+    // Fractions of a second do not matter for ranges
+    if rest.starts_with('.') {
+        rest = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    }
+    match rest.chars().next() {
+        None | Some('Z') | Some('z') => Some(seconds),
+        Some(sign @ ('+' | '-')) => {
+            let offset = parse_number(rest, 1, 2)? * 3600 + parse_number(rest, 4, 2)? * 60;
+            match sign {
+                '+' => Some(seconds - offset),
+                _ => Some(seconds + offset),
+            }
+        }
+        _ => None,
+    }
+}
+
+//
+// A YYYY-MM-DD upper bound takes in the whole day
+//
+fn parse_upper_timestamp(bound: &str) -> Option<i64> {
+    let timestamp = parse_timestamp(&serde_json::json!(bound))?;
+    if bound.trim().len() == 10 {
+        return Some(timestamp + 86399);
+    }
+    Some(timestamp)
+}
+
+fn values_equal(left: &serde_json::Value, right: &serde_json::Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+impl DeepThoughtFilter {
+    pub fn equals(field: &str, value: impl Into<serde_json::Value>) -> Self {
+        DeepThoughtFilter::Eq(field.to_string(), value.into())
+    }
+
+    pub fn not_equals(field: &str, value: impl Into<serde_json::Value>) -> Self {
+        DeepThoughtFilter::Ne(field.to_string(), value.into())
+    }
+
+    pub fn one_of(field: &str, values: Vec<serde_json::Value>) -> Self {
+        DeepThoughtFilter::In(field.to_string(), values)
+    }
+
+    pub fn range(field: &str, min: Option<f64>, max: Option<f64>) -> Self {
+        DeepThoughtFilter::Range {
+            field: field.to_string(),
+            min,
+            max,
+        }
+    }
+
+    pub fn date_range(field: &str, from: Option<&str>, to: Option<&str>) -> Self {
+        DeepThoughtFilter::DateRange {
+            field: field.to_string(),
+            from: from.map(|from| from.to_string()),
+            to: to.map(|to| to.to_string()),
+        }
+    }
+
+    pub fn exists(field: &str) -> Self {
+        DeepThoughtFilter::Exists(field.to_string())
+    }
+
+    pub fn and(self, other: DeepThoughtFilter) -> Self {
+        match self {
+            DeepThoughtFilter::And(mut filters) => {
+                filters.push(other);
+                DeepThoughtFilter::And(filters)
+            }
+            filter => DeepThoughtFilter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: DeepThoughtFilter) -> Self {
+        match self {
+            DeepThoughtFilter::Or(mut filters) => {
+                filters.push(other);
+                DeepThoughtFilter::Or(filters)
+            }
+            filter => DeepThoughtFilter::Or(vec![filter, other]),
+        }
+    }
+
+    pub fn negate(self) -> Self {
+        DeepThoughtFilter::Not(Box::new(self))
+    }
+
+    //
+    // Field is looked up by its full name first, so "tag.type" stored by
+    // add_object matches as is, then as a path into nested objects
+    //
+    fn lookup<'a>(
+        metadata: &'a HashMap<String, serde_json::Value>,
+        field: &str,
+    ) -> Option<&'a serde_json::Value> {
+        match metadata.get(field) {
+            Some(value) => Some(value),
+            None => {
+                let mut parts = field.split('.');
+                let mut value = metadata.get(parts.next()?)?;
+                for part in parts {
+                    value = value.get(part)?;
+                }
+                Some(value)
+            }
+        }
+    }
+
+    pub fn matches(&self, metadata: &HashMap<String, serde_json::Value>) -> bool {
+        match self {
+            DeepThoughtFilter::Eq(field, expected) => {
+                match DeepThoughtFilter::lookup(metadata, field) {
+                    Some(value) => values_equal(value, expected),
+                    None => false,
+                }
+            }
+            DeepThoughtFilter::Ne(field, expected) => {
+                match DeepThoughtFilter::lookup(metadata, field) {
+                    Some(value) => !values_equal(value, expected),
+                    None => true,
+                }
+            }
+            DeepThoughtFilter::In(field, expected) => {
+                match DeepThoughtFilter::lookup(metadata, field) {
+                    Some(value) => expected
+                        .iter()
+                        .any(|expected| values_equal(value, expected)),
+                    None => false,
+                }
+            }
+            DeepThoughtFilter::Range { field, min, max } => {
+                let value = match DeepThoughtFilter::lookup(metadata, field) {
+                    Some(value) => match value.as_f64() {
+                        Some(value) => value,
+                        None => return false,
+                    },
+                    None => return false,
+                };
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+            }
+            DeepThoughtFilter::DateRange { field, from, to } => {
+                let value = match DeepThoughtFilter::lookup(metadata, field) {
+                    Some(value) => match parse_timestamp(value) {
+                        Some(value) => value,
+                        None => return false,
+                    },
+                    None => return false,
+                };
+                // A bound that is not a date never matches
+                let after_from = match from {
+                    Some(from) => match parse_timestamp(&serde_json::json!(from)) {
+                        Some(from) => value >= from,
+                        None => false,
+                    },
+                    None => true,
+                };
+                let before_to = match to {
+                    Some(to) => match parse_upper_timestamp(to) {
+                        Some(to) => value <= to,
+                        None => false,
+                    },
+                    None => true,
+                };
+                after_from && before_to
+            }
+            DeepThoughtFilter::Exists(field) => {
+                DeepThoughtFilter::lookup(metadata, field).is_some()
+            }
+            DeepThoughtFilter::And(filters) => filters.iter().all(|f| f.matches(metadata)),
+            DeepThoughtFilter::Or(filters) => filters.iter().any(|f| f.matches(metadata)),
+            DeepThoughtFilter::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+extern crate log;
+
+use crate::*;
+
+use std::io::Write;
+
+use llama_cpp_2::{context::LlamaContext, llama_batch::LlamaBatch, model::Special};
+
+impl SamplingParams {
+    //
+    // Position of the earliest stop sequence found in the generated bytes
+    //
+    pub fn find_stop(&self, text: &[u8]) -> Option<usize> {
+        let mut found: Option<usize> = None;
+        for stop in self.stop.iter() {
+            let stop = stop.as_bytes();
+            if stop.is_empty() || stop.len() > text.len() {
+                continue;
+            }
+            match text.windows(stop.len()).position(|window| window == stop) {
+                Some(pos) => {
+                    found = match found {
+                        Some(prev) if prev <= pos => Some(prev),
+                        _ => Some(pos),
+                    }
+                }
+                None => {}
+            }
+        }
+        found
+    }
+
+    //
+    // Number of trailing bytes that may still become a stop sequence and
+    // therefore must not be written to the output yet
+    //
+    pub fn stop_holdback(&self, text: &[u8]) -> usize {
+        let mut holdback = 0;
+        for stop in self.stop.iter() {
+            let stop = stop.as_bytes();
+            let longest = std::cmp::min(stop.len().saturating_sub(1), text.len());
+            for n in (holdback + 1..=longest).rev() {
+                if text[text.len() - n..] == stop[..n] {
+                    holdback = n;
+                    break;
+                }
+            }
+        }
+        holdback
+    }
+
+    //
+    // Samples tokens after the prompt already decoded into the context until
+    // end of generation, a stop sequence, max_new_tokens or a full context.
+    // Every token decoded into the context is appended to tokens.
+    //
+    pub fn generate(
+        &self,
+        model: &LlamaModel,
+        context: &mut LlamaContext,
+        batch: &mut LlamaBatch,
+        tokens: &mut Vec<LlamaToken>,
+        n_len: i32,
+        output: &mut impl Write,
+    ) -> Result<DeepThoughtFinishReason, Error> {
+        // LlamaSampler::sample() accepts the token into the chain by itself,
+        // accepting it again would count penalties twice.
+        let mut sampler = self.sampler(model)?;
+        let mut n_cur = tokens.len() as i32;
+        let mut generated: Vec<u8> = Vec::new();
+        let mut emitted: usize = 0;
+        let mut n_new: usize = 0;
+        let finish_reason = loop {
+            match self.max_new_tokens {
+                Some(max_new_tokens) if n_new >= max_new_tokens => {
+                    break DeepThoughtFinishReason::MaxTokens;
+                }
+                _ => {}
+            }
+            let token = sampler.sample(context, batch.n_tokens() - 1);
+
+            if model.is_eog_token(token) {
+                break DeepThoughtFinishReason::Eog;
+            }
+
+            let output_bytes = model.token_to_bytes(token, Special::Tokenize)?;
+            generated.extend_from_slice(&output_bytes);
+            n_new += 1;
+
+            match self.find_stop(&generated[emitted..]) {
+                Some(pos) => {
+                    output.write_all(&generated[emitted..emitted + pos])?;
+                    output.flush()?;
+                    emitted = generated.len();
+                    break DeepThoughtFinishReason::StopSequence;
+                }
+                None => {}
+            }
+            let safe = generated.len() - self.stop_holdback(&generated[emitted..]);
+            if safe > emitted {
+                output.write_all(&generated[emitted..safe])?;
+                output.flush()?;
+                emitted = safe;
+            }
+
+            if n_cur >= n_len {
+                break DeepThoughtFinishReason::ContextFull;
+            }
+
+            batch.clear();
+            batch.add(token, n_cur, &[0], true)?;
+
+            n_cur += 1;
+
+            match context.decode(batch) {
+                Ok(_) => {}
+                Err(_) => return Err(Error::InternalNativeError("Decoding error".to_string())),
+            };
+            tokens.push(token);
+        };
+        if emitted < generated.len() {
+            output.write_all(&generated[emitted..])?;
+            output.flush()?;
+        }
+        Ok(finish_reason)
+    }
+}
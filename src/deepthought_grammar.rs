@@ -0,0 +1,260 @@
+extern crate log;
+
+use easy_error::bail;
+
+use crate::*;
+
+//
+// Shared GBNF rules for JSON values, every value rule consumes trailing whitespace
+//
+pub const JSON_GRAMMAR_RULES: &str = r#"value ::= object | array | string | number | boolean | null
+object ::= "{" ws ( string ":" ws value ( "," ws string ":" ws value )* )? "}" ws
+array ::= "[" ws ( value ( "," ws value )* )? "]" ws
+string ::= "\"" char* "\"" ws
+char ::= [^"\\\x7F\x00-\x1F] | "\\" ( ["\\/bfnrt] | "u" [0-9a-fA-F]{4} )
+number ::= integer-part ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )? ws
+integer ::= integer-part ws
+integer-part ::= "-"? ( [0-9] | [1-9] [0-9]{0,15} )
+boolean ::= ( "true" | "false" ) ws
+null ::= "null" ws
+ws ::= | " " | "\n" [ \t]{0,20}
+"#;
+
+impl DeepThoughtGrammar {
+    //
+    // Grammar accepting any JSON object
+    //
+    pub fn json() -> String {
+        format!("root ::= object\n{}", JSON_GRAMMAR_RULES)
+    }
+
+    pub fn from_type<T: DeepThoughtJsonSchema>() -> Result<String, easy_error::Error> {
+        DeepThoughtGrammar::from_json_schema(&T::json_schema())
+    }
+
+    pub fn from_json_schema(schema: &serde_json::Value) -> Result<String, easy_error::Error> {
+        let mut rules: Vec<(String, String)> = Vec::new();
+        let root = match DeepThoughtGrammar::visit(schema, "root", &mut rules) {
+            Ok(root) => root,
+            Err(err) => bail!("Error converting JSON schema to grammar: {}", err),
+        };
+        let mut grammar = String::new();
+        if root != "root" {
+            grammar.push_str(&format!("root ::= {}\n", root));
+        }
+        for (name, body) in rules.iter() {
+            grammar.push_str(&format!("{} ::= {}\n", name, body));
+        }
+        grammar.push_str(JSON_GRAMMAR_RULES);
+        Ok(grammar)
+    }
+
+    //
+    // Quotes text as a GBNF string literal
+    //
+    pub fn literal(text: &str) -> String {
+        let mut res = String::with_capacity(text.len() + 2);
+        res.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => res.push_str("\\\""),
+                '\\' => res.push_str("\\\\"),
+                '\n' => res.push_str("\\n"),
+                '\r' => res.push_str("\\r"),
+                '\t' => res.push_str("\\t"),
+                _ => res.push(c),
+            }
+        }
+        res.push('"');
+        res
+    }
+
+    fn json_literal(value: &serde_json::Value) -> String {
+        format!("{} ws", DeepThoughtGrammar::literal(&value.to_string()))
+    }
+
+    fn rule_name(name: &str, rules: &Vec<(String, String)>) -> String {
+        let base: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut candidate = base.clone();
+        let mut n = 1;
+        while rules.iter().any(|(existing, _)| existing == &candidate) {
+            candidate = format!("{}-{}", base, n);
+            n += 1;
+        }
+        candidate
+    }
+
+    fn add_rule(name: &str, body: String, rules: &mut Vec<(String, String)>) -> String {
+        let name = DeepThoughtGrammar::rule_name(name, rules);
+        rules.push((name.clone(), body));
+        name
+    }
+
+    fn visit(
+        schema: &serde_json::Value,
+        name: &str,
+        rules: &mut Vec<(String, String)>,
+    ) -> Result<String, easy_error::Error> {
+        let obj = match schema {
+            serde_json::Value::Bool(true) => return Ok("value".to_string()),
+            serde_json::Value::Object(obj) => obj,
+            _ => bail!("Unsupported schema: {}", schema),
+        };
+        if obj.contains_key("$ref") {
+            bail!("$ref is not supported in schema {}", name);
+        }
+        match obj.get("const") {
+            Some(value) => return Ok(DeepThoughtGrammar::json_literal(value)),
+            None => {}
+        }
+        match obj.get("enum").and_then(|values| values.as_array()) {
+            Some(values) => {
+                let alternatives: Vec<String> = values
+                    .iter()
+                    .map(|value| DeepThoughtGrammar::json_literal(value))
+                    .collect();
+                return Ok(format!("( {} )", alternatives.join(" | ")));
+            }
+            None => {}
+        }
+        for key in ["anyOf", "oneOf"] {
+            match obj.get(key).and_then(|schemas| schemas.as_array()) {
+                Some(schemas) => {
+                    let mut alternatives: Vec<String> = Vec::new();
+                    for (n, sub_schema) in schemas.iter().enumerate() {
+                        let sub_name = format!("{}-{}", name, n);
+                        alternatives.push(DeepThoughtGrammar::visit(sub_schema, &sub_name, rules)?);
+                    }
+                    return Ok(format!("( {} )", alternatives.join(" | ")));
+                }
+                None => {}
+            }
+        }
+        match obj.get("type") {
+            Some(serde_json::Value::String(schema_type)) => {
+                DeepThoughtGrammar::visit_type(schema_type, obj, name, rules)
+            }
+            Some(serde_json::Value::Array(schema_types)) => {
+                let mut alternatives: Vec<String> = Vec::new();
+                for schema_type in schema_types.iter() {
+                    match schema_type.as_str() {
+                        Some(schema_type) => alternatives.push(DeepThoughtGrammar::visit_type(
+                            schema_type,
+                            obj,
+                            name,
+                            rules,
+                        )?),
+                        None => bail!("Unsupported type in schema {}", name),
+                    }
+                }
+                Ok(format!("( {} )", alternatives.join(" | ")))
+            }
+            Some(schema_type) => bail!("Unsupported type {} in schema {}", schema_type, name),
+            None => {
+                if obj.contains_key("properties") || obj.contains_key("additionalProperties") {
+                    DeepThoughtGrammar::visit_type("object", obj, name, rules)
+                } else if obj.contains_key("items") {
+                    DeepThoughtGrammar::visit_type("array", obj, name, rules)
+                } else {
+                    Ok("value".to_string())
+                }
+            }
+        }
+    }
+
+    fn visit_type(
+        schema_type: &str,
+        obj: &serde_json::Map<String, serde_json::Value>,
+        name: &str,
+        rules: &mut Vec<(String, String)>,
+    ) -> Result<String, easy_error::Error> {
+        match schema_type {
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(schema_type.to_string()),
+            "array" => {
+                let item = match obj.get("items") {
+                    Some(items) => DeepThoughtGrammar::visit(items, &format!("{}-item", name), rules)?,
+                    None => "value".to_string(),
+                };
+                let body = format!(
+                    "\"[\" ws ( {} ( \",\" ws {} )* )? \"]\" ws",
+                    item, item
+                );
+                Ok(DeepThoughtGrammar::add_rule(name, body, rules))
+            }
+            "object" => DeepThoughtGrammar::visit_object(obj, name, rules),
+            _ => bail!("Unsupported type {} in schema {}", schema_type, name),
+        }
+    }
+
+    fn visit_object(
+        obj: &serde_json::Map<String, serde_json::Value>,
+        name: &str,
+        rules: &mut Vec<(String, String)>,
+    ) -> Result<String, easy_error::Error> {
+        let properties = match obj.get("properties").and_then(|props| props.as_object()) {
+            Some(properties) if !properties.is_empty() => properties,
+            _ => {
+                let body = match obj.get("additionalProperties") {
+                    Some(serde_json::Value::Bool(false)) => "\"{\" ws \"}\" ws".to_string(),
+                    Some(serde_json::Value::Object(value_schema)) if !value_schema.is_empty() => {
+                        let value = DeepThoughtGrammar::visit(
+                            &serde_json::Value::Object(value_schema.clone()),
+                            &format!("{}-value", name),
+                            rules,
+                        )?;
+                        format!(
+                            "\"{{\" ws ( string \":\" ws {} ( \",\" ws string \":\" ws {} )* )? \"}}\" ws",
+                            value, value
+                        )
+                    }
+                    _ => return Ok("object".to_string()),
+                };
+                return Ok(DeepThoughtGrammar::add_rule(name, body, rules));
+            }
+        };
+        let required: Vec<&str> = match obj.get("required").and_then(|req| req.as_array()) {
+            Some(required) => required.iter().filter_map(|key| key.as_str()).collect(),
+            None => Vec::new(),
+        };
+        let mut required_kv: Vec<String> = Vec::new();
+        let mut optional_kv: Vec<String> = Vec::new();
+        for (key, prop_schema) in properties.iter() {
+            let value = DeepThoughtGrammar::visit(prop_schema, &format!("{}-{}", name, key), rules)?;
+            let kv = format!(
+                "{} ws \":\" ws {}",
+                DeepThoughtGrammar::literal(&serde_json::Value::String(key.clone()).to_string()),
+                value
+            );
+            if required.contains(&key.as_str()) {
+                required_kv.push(kv);
+            } else {
+                optional_kv.push(kv);
+            }
+        }
+        let mut body = String::from("\"{\" ws ");
+        if required_kv.is_empty() {
+            // Without a required property to anchor the commas, the first
+            // optional one has to be present before any of the rest
+            let mut chain = String::new();
+            for (n, kv) in optional_kv.iter().enumerate() {
+                if n == 0 {
+                    chain.push_str(kv);
+                } else {
+                    chain.push_str(&format!(" ( \",\" ws {} )?", kv));
+                }
+            }
+            body.push_str(&format!("( {} )? ", chain));
+        } else {
+            body.push_str(&required_kv.join(" \",\" ws "));
+            for kv in optional_kv.iter() {
+                body.push_str(&format!(" ( \",\" ws {} )?", kv));
+            }
+            body.push(' ');
+        }
+        body.push_str("\"}\" ws");
+        Ok(DeepThoughtGrammar::add_rule(name, body, rules))
+    }
+}
@@ -0,0 +1,68 @@
+extern crate log;
+
+use crate::*;
+
+pub const DEFAULT_ANSWER_RESERVE: usize = 1024;
+
+impl Default for DeepThoughtTokenBudget {
+    fn default() -> Self {
+        DeepThoughtTokenBudget {
+            max_tokens: None,
+            reserve: DEFAULT_ANSWER_RESERVE,
+        }
+    }
+}
+
+impl DeepThoughtTokenBudget {
+    pub fn new(max_tokens: usize, reserve: usize) -> Self {
+        DeepThoughtTokenBudget {
+            max_tokens: Some(max_tokens),
+            reserve,
+        }
+    }
+
+    pub fn limit(&self, context_length: usize) -> usize {
+        let max_tokens = match self.max_tokens {
+            Some(max_tokens) => std::cmp::min(max_tokens, context_length),
+            None => context_length,
+        };
+        max_tokens.saturating_sub(self.reserve)
+    }
+
+    //
+    // Drops the oldest droppable messages (never the last one) until
+    // count_tokens(messages) fits the limit, returns how many were dropped
+    //
+    pub fn fit(
+        &self,
+        messages: &mut Vec<DeepThoughtMessage>,
+        context_length: usize,
+        count_tokens: impl Fn(&[DeepThoughtMessage]) -> Result<usize, Error>,
+    ) -> Result<usize, Error> {
+        let limit = self.limit(context_length);
+        let mut dropped = 0;
+        loop {
+            let actual = count_tokens(messages)?;
+            if actual <= limit {
+                return Ok(dropped);
+            }
+            let last_index = messages.len().saturating_sub(1);
+            match messages
+                .iter()
+                .position(|msg| msg.is_droppable())
+                .filter(|n| *n < last_index)
+            {
+                Some(n) => {
+                    messages.remove(n);
+                    dropped += 1;
+                }
+                None => {
+                    return Err(Error::ContextSize {
+                        maximum: limit,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+extern crate log;
+
+use easy_error::bail;
+
+use crate::*;
+
+use llama_cpp_2::model::Special;
+
+fn token_text(model: &LlamaModel, token: LlamaToken) -> Option<String> {
+    match model.token_to_bytes(token, Special::Tokenize) {
+        Ok(bytes) => Some(String::from_utf8_lossy(&bytes).to_string()),
+        Err(_) => None,
+    }
+}
+
+impl DeepThoughtModelInfo {
+    pub fn from_model(model: &LlamaModel) -> Self {
+        let mut metadata: HashMap<String, String> = HashMap::new();
+        for n in 0..model.meta_count() {
+            match (model.meta_key_by_index(n), model.meta_val_str_by_index(n)) {
+                (Ok(key), Ok(value)) => {
+                    metadata.insert(key, value);
+                }
+                _ => log::debug!("Skipping unreadable GGUF metadata entry {}", n),
+            }
+        }
+        let mut special_tokens: HashMap<String, String> = HashMap::new();
+        for (name, token) in [
+            ("bos", model.token_bos()),
+            ("eos", model.token_eos()),
+            ("nl", model.token_nl()),
+        ] {
+            match token_text(model, token) {
+                Some(text) => {
+                    special_tokens.insert(name.to_string(), text);
+                }
+                None => {}
+            }
+        }
+        DeepThoughtModelInfo {
+            architecture: metadata.get("general.architecture").cloned(),
+            name: metadata.get("general.name").cloned(),
+            n_params: model.n_params(),
+            size: model.size(),
+            n_vocab: model.n_vocab(),
+            n_ctx_train: model.n_ctx_train(),
+            n_embd: model.n_embd(),
+            chat_template: metadata.get("tokenizer.chat_template").cloned(),
+            special_tokens,
+            metadata,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut res: HashMap<String, Value> = HashMap::new();
+        match self.architecture {
+            Some(ref architecture) => {
+                res.insert(
+                    "architecture".to_string(),
+                    Value::from_string(architecture.clone()),
+                );
+            }
+            None => {}
+        }
+        match self.name {
+            Some(ref name) => {
+                res.insert("name".to_string(), Value::from_string(name.clone()));
+            }
+            None => {}
+        }
+        match self.chat_template {
+            Some(ref chat_template) => {
+                res.insert(
+                    "chat_template".to_string(),
+                    Value::from_string(chat_template.clone()),
+                );
+            }
+            None => {}
+        }
+        res.insert(
+            "n_params".to_string(),
+            Value::from_int(self.n_params as i64),
+        );
+        res.insert("size".to_string(), Value::from_int(self.size as i64));
+        res.insert("n_vocab".to_string(), Value::from_int(self.n_vocab as i64));
+        res.insert(
+            "n_ctx_train".to_string(),
+            Value::from_int(self.n_ctx_train as i64),
+        );
+        res.insert("n_embd".to_string(), Value::from_int(self.n_embd as i64));
+        let mut special_tokens: HashMap<String, Value> = HashMap::new();
+        for (key, value) in self.special_tokens.iter() {
+            special_tokens.insert(key.clone(), Value::from_string(value.clone()));
+        }
+        res.insert(
+            "special_tokens".to_string(),
+            Value::from_dict(special_tokens),
+        );
+        let mut metadata: HashMap<String, Value> = HashMap::new();
+        for (key, value) in self.metadata.iter() {
+            metadata.insert(key.clone(), Value::from_string(value.clone()));
+        }
+        res.insert("metadata".to_string(), Value::from_dict(metadata));
+        Value::from_dict(res)
+    }
+
+    //
+    // Context length must fit into what the model was trained on,
+    // n_ctx_train of 0 means the model does not report it
+    //
+    pub fn check_context_length(&self, context_length: usize) -> Result<(), easy_error::Error> {
+        if self.n_ctx_train > 0 && context_length > self.n_ctx_train as usize {
+            bail!(
+                "Context length {} exceeds trained context {} of {}",
+                context_length,
+                self.n_ctx_train,
+                self.name.clone().unwrap_or("model".to_string())
+            );
+        }
+        Ok(())
+    }
+
+    pub fn check_embedding_dimension(&self, dimension: usize) -> Result<(), easy_error::Error> {
+        if self.n_embd as usize != dimension {
+            bail!(
+                "Embedding model {} produces {} dimensions, expected {}",
+                self.name.clone().unwrap_or("model".to_string()),
+                self.n_embd,
+                dimension
+            );
+        }
+        Ok(())
+    }
+}
+
+impl DeepThoughtModel {
+    pub fn info(&self) -> DeepThoughtModelInfo {
+        DeepThoughtModelInfo::from_model(&self.model)
+    }
+}
+
+impl DeepThoughtCtxModel {
+    pub fn info(&self) -> DeepThoughtModelInfo {
+        DeepThoughtModelInfo::from_model(&self.model)
+    }
+}
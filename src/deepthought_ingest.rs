@@ -0,0 +1,538 @@
+extern crate log;
+
+use easy_error::bail;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use took::Timer;
+use walkdir::WalkDir;
+
+use crate::deepthought_extract::html_to_text;
+use crate::*;
+
+//
+// Hex encoded SHA-256 of the file content, stored as content_hash on every chunk
+//
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+//
+// Document id of a source file is derived from its path, so ingesting the
+// same file again replaces its chunks
+//
+pub fn source_doc_id(source: &str) -> String {
+    format!("file-{}", &content_hash(source.as_bytes())[..16])
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet, easy_error::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(err) => bail!("Invalid glob pattern {}: {}", pattern, err),
+        }
+    }
+    match builder.build() {
+        Ok(globset) => Ok(globset),
+        Err(err) => bail!("Error building glob patterns: {}", err),
+    }
+}
+
+//
+// Directories a pattern like target/** excludes as a whole, so the walk
+// never enters them
+//
+fn build_dir_globset(patterns: &[String]) -> Result<GlobSet, easy_error::Error> {
+    let dirs: Vec<String> = patterns
+        .iter()
+        .filter_map(|pattern| pattern.strip_suffix("/**"))
+        .map(|dir| dir.to_string())
+        .collect();
+    build_globset(&dirs)
+}
+
+fn json_lines(value: &serde_json::Value, prefix: &str, res: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter() {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                json_lines(value, &key, res);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for (n, value) in values.iter().enumerate() {
+                json_lines(value, &format!("{}[{}]", prefix, n), res);
+            }
+        }
+        serde_json::Value::String(text) => res.push(format!("{}: {}", prefix, text)),
+        serde_json::Value::Null => {}
+        value => res.push(format!("{}: {}", prefix, value)),
+    }
+}
+
+//
+// JSON is flattened to "path: value" lines, which embed better than raw JSON
+//
+pub fn json_to_text(content: &str) -> Result<String, easy_error::Error> {
+    let value: serde_json::Value = match serde_json::from_str(content) {
+        Ok(value) => value,
+        Err(err) => bail!("Error parsing JSON: {}", err),
+    };
+    let mut res: Vec<String> = Vec::new();
+    json_lines(&value, "", &mut res);
+    Ok(res.join("\n"))
+}
+
+pub fn jsonl_to_text(content: &str) -> Result<String, easy_error::Error> {
+    let mut records: Vec<String> = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = match serde_json::from_str(line) {
+            Ok(value) => value,
+            Err(err) => bail!("Error parsing JSON on line {}: {}", n + 1, err),
+        };
+        let mut res: Vec<String> = Vec::new();
+        json_lines(&value, "", &mut res);
+        records.push(res.join("; "));
+    }
+    Ok(records.join("\n"))
+}
+
+fn csv_records(content: &str) -> Vec<Vec<String>> {
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+        .into_iter()
+        .filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+        .collect()
+}
+
+//
+// Every CSV row becomes a line of "header: value" pairs
+//
+pub fn csv_to_text(content: &str) -> String {
+    let records = csv_records(content);
+    let header = match records.first() {
+        Some(header) => header.clone(),
+        None => return String::new(),
+    };
+    let mut res: Vec<String> = Vec::new();
+    for record in records.iter().skip(1) {
+        let fields: Vec<String> = record
+            .iter()
+            .enumerate()
+            .map(|(n, value)| match header.get(n) {
+                Some(name) => format!("{}: {}", name.trim(), value.trim()),
+                None => value.trim().to_string(),
+            })
+            .collect();
+        res.push(fields.join("; "));
+    }
+    res.join("\n")
+}
+
+//
+// Sources are recorded by canonical path, so the same file reached through
+// different relative paths is one document
+//
+pub(crate) fn source_path(path: &Path) -> String {
+    match std::fs::canonicalize(path) {
+        Ok(source) => source.display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+fn modified(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+impl DeepThoughtFileType {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "txt" | "text" | "log" => Some(DeepThoughtFileType::Text),
+            "md" | "markdown" => Some(DeepThoughtFileType::Markdown),
+            "html" | "htm" => Some(DeepThoughtFileType::Html),
+            "json" => Some(DeepThoughtFileType::Json),
+            "jsonl" | "ndjson" => Some(DeepThoughtFileType::JsonLines),
+            "csv" => Some(DeepThoughtFileType::Csv),
+            extension => {
+                DeepThoughtLanguage::from_extension(extension).map(DeepThoughtFileType::Code)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeepThoughtFileType::Text => "text",
+            DeepThoughtFileType::Markdown => "markdown",
+            DeepThoughtFileType::Html => "html",
+            DeepThoughtFileType::Json => "json",
+            DeepThoughtFileType::JsonLines => "jsonl",
+            DeepThoughtFileType::Csv => "csv",
+            DeepThoughtFileType::Code(language) => language.name(),
+        }
+    }
+
+    //
+    // Splitter matching the structure of the extracted text, plain text keeps
+    // the splitter configured for the store
+    //
+    pub fn splitter(&self, default: DeepThoughtSplitter) -> DeepThoughtSplitter {
+        match self {
+            DeepThoughtFileType::Markdown | DeepThoughtFileType::Html => {
+                DeepThoughtSplitter::Markdown
+            }
+            DeepThoughtFileType::Code(language) => DeepThoughtSplitter::Code(*language),
+            DeepThoughtFileType::Json
+            | DeepThoughtFileType::JsonLines
+            | DeepThoughtFileType::Csv => DeepThoughtSplitter::Lines,
+            DeepThoughtFileType::Text => default,
+        }
+    }
+
+    pub fn extract_text(&self, content: &str) -> Result<String, easy_error::Error> {
+        match self {
+            DeepThoughtFileType::Text
+            | DeepThoughtFileType::Markdown
+            | DeepThoughtFileType::Code(_) => Ok(content.to_string()),
+            DeepThoughtFileType::Html => Ok(html_to_text(content)),
+            DeepThoughtFileType::Json => json_to_text(content),
+            DeepThoughtFileType::JsonLines => jsonl_to_text(content),
+            DeepThoughtFileType::Csv => Ok(csv_to_text(content)),
+        }
+    }
+}
+
+impl DeepThoughtIngestOptions {
+    pub fn new() -> Self {
+        DeepThoughtIngestOptions::default()
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    pub fn max_file_size(mut self, size: u64) -> Self {
+        self.max_file_size = Some(size);
+        self
+    }
+
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    //
+    // Re-embeds files even when their content hash did not change
+    //
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+}
+
+impl DeepThoughtIngestResult {
+    pub(crate) fn new(path: &Path, status: DeepThoughtIngestStatus, timer: &Timer) -> Self {
+        DeepThoughtIngestResult {
+            path: path.display().to_string(),
+            status,
+            doc_id: None,
+            chunks: 0,
+            duration: *timer.took().as_std(),
+        }
+    }
+}
+
+impl DeepThoughtVecStore {
+    //
+    // Ingests a file or every matching file under a directory, a failing
+    // file is reported in its result and does not stop the walk. The path
+    // and options are remembered for sync_sources.
+    //
+    pub fn ingest_path(
+        &mut self,
+        path: &str,
+        embedder: &DeepThoughtModel,
+        options: &DeepThoughtIngestOptions,
+    ) -> Result<Vec<DeepThoughtIngestResult>, easy_error::Error> {
+        let root = Path::new(path);
+        if !root.exists() {
+            bail!("Path not found: {}", path);
+        }
+        let walk = self.walk_path(root, embedder, options, false)?;
+        self.sources.insert(source_path(root), options.clone());
+        Ok(walk.results)
+    }
+
+    //
+    // Returns results of every matching file, the sources it saw and the
+    // paths it could not read
+    //
+    pub(crate) fn walk_path(
+        &mut self,
+        root: &Path,
+        embedder: &DeepThoughtModel,
+        options: &DeepThoughtIngestOptions,
+        dry_run: bool,
+    ) -> Result<DeepThoughtWalk, easy_error::Error> {
+        let mut walk = DeepThoughtWalk::default();
+        let include = build_globset(&options.include)?;
+        let exclude = build_globset(&options.exclude)?;
+        let selected = |relative: &Path| {
+            (options.include.is_empty() || include.is_match(relative))
+                && !exclude.is_match(relative)
+        };
+        if root.is_file() {
+            // A single file is matched by its name
+            let name = Path::new(root.file_name().unwrap_or(root.as_os_str()));
+            if !selected(name) {
+                walk.results.push(DeepThoughtIngestResult::new(
+                    root,
+                    DeepThoughtIngestStatus::Skipped("excluded by glob patterns".to_string()),
+                    &Timer::new(),
+                ));
+                return Ok(walk);
+            }
+            walk.seen.insert(source_path(root));
+            walk.results
+                .push(self.ingest_file_with(root, embedder, options, dry_run));
+            return Ok(walk);
+        }
+        let exclude_dirs = build_dir_globset(&options.exclude)?;
+        let walker = WalkDir::new(root)
+            .follow_links(options.follow_links)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                if entry.depth() == 0 || !entry.file_type().is_dir() {
+                    return true;
+                }
+                let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                !exclude_dirs.is_match(relative) && !exclude.is_match(relative)
+            });
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let failed_path = err.path().unwrap_or(root).to_path_buf();
+                    // Keep what could not be read, a walk error is not a removal
+                    walk.failed.push(source_path(&failed_path));
+                    walk.results.push(DeepThoughtIngestResult::new(
+                        &failed_path,
+                        DeepThoughtIngestStatus::Failed(err.to_string()),
+                        &Timer::new(),
+                    ));
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            if !selected(relative) {
+                continue;
+            }
+            walk.seen.insert(source_path(entry.path()));
+            walk.results
+                .push(self.ingest_file_with(entry.path(), embedder, options, dry_run));
+        }
+        Ok(walk)
+    }
+
+    pub fn ingest_file(
+        &mut self,
+        path: &Path,
+        embedder: &DeepThoughtModel,
+        options: &DeepThoughtIngestOptions,
+    ) -> DeepThoughtIngestResult {
+        self.ingest_file_with(path, embedder, options, false)
+    }
+
+    //
+    // With dry_run the file is only checked against its stored content hash
+    //
+    fn ingest_file_with(
+        &mut self,
+        path: &Path,
+        embedder: &DeepThoughtModel,
+        options: &DeepThoughtIngestOptions,
+        dry_run: bool,
+    ) -> DeepThoughtIngestResult {
+        let timer = Timer::new();
+        let file_type = match DeepThoughtFileType::from_path(path) {
+            Some(file_type) => file_type,
+            None => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Skipped("unsupported file type".to_string()),
+                    &timer,
+                );
+            }
+        };
+        // Oversized files are skipped before anything is read into memory
+        let size = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Failed(err.to_string()),
+                    &timer,
+                );
+            }
+        };
+        match options.max_file_size {
+            Some(max_file_size) if size > max_file_size => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Skipped(format!(
+                        "larger than {} bytes",
+                        max_file_size
+                    )),
+                    &timer,
+                );
+            }
+            _ => {}
+        }
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Failed(err.to_string()),
+                    &timer,
+                );
+            }
+        };
+        let content = match String::from_utf8(data) {
+            Ok(content) => content,
+            Err(_) => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Skipped("not UTF-8 text".to_string()),
+                    &timer,
+                );
+            }
+        };
+        let hash = content_hash(content.as_bytes());
+        let source = source_path(path);
+        let doc_id = source_doc_id(&source);
+        let previous_hash = match self.documents.get(&doc_id) {
+            Some(document) => document.metadata.get("content_hash").cloned(),
+            None => None,
+        };
+        if !options.force && previous_hash == Some(serde_json::json!(hash)) {
+            let mut res =
+                DeepThoughtIngestResult::new(path, DeepThoughtIngestStatus::Unchanged, &timer);
+            res.chunks = self.chunk_ids(&doc_id).len();
+            res.doc_id = Some(doc_id);
+            return res;
+        }
+        let status = match previous_hash {
+            Some(_) => DeepThoughtIngestStatus::Updated,
+            None => DeepThoughtIngestStatus::Added,
+        };
+        if dry_run {
+            let mut res = DeepThoughtIngestResult::new(path, status, &timer);
+            res.doc_id = Some(doc_id);
+            return res;
+        }
+        let mut metadata = options.metadata.clone();
+        metadata.insert("source".to_string(), serde_json::json!(source));
+        match path.file_name() {
+            Some(file_name) => {
+                metadata.insert(
+                    "file_name".to_string(),
+                    serde_json::json!(file_name.to_string_lossy()),
+                );
+            }
+            None => {}
+        }
+        metadata.insert("file_type".to_string(), serde_json::json!(file_type.name()));
+        metadata.insert("content_hash".to_string(), serde_json::json!(hash));
+        metadata.insert("size".to_string(), serde_json::json!(content.len()));
+        match modified(path) {
+            Some(modified) => {
+                metadata.insert("modified".to_string(), serde_json::json!(modified));
+            }
+            None => {}
+        }
+        // HTML and Markdown keep their headings for title and anchor metadata
+        let added = match file_type {
+            DeepThoughtFileType::Html => self.add_html(&doc_id, &content, embedder, &metadata),
+            DeepThoughtFileType::Markdown => {
+                self.add_markdown(&doc_id, &content, embedder, &metadata)
+            }
+            file_type => match file_type.extract_text(&content) {
+                Ok(text) => {
+                    let splitter = file_type.splitter(self.splitter);
+                    self.add_document_with(&doc_id, &text, embedder, splitter, &metadata)
+                }
+                Err(err) => Err(err),
+            },
+        };
+        match added {
+            Ok(_) => {}
+            Err(err) => {
+                return DeepThoughtIngestResult::new(
+                    path,
+                    DeepThoughtIngestStatus::Failed(err.to_string()),
+                    &timer,
+                );
+            }
+        }
+        let mut res = DeepThoughtIngestResult::new(path, status, &timer);
+        res.chunks = self.chunk_ids(&doc_id).len();
+        res.doc_id = Some(doc_id);
+        res
+    }
+}
@@ -0,0 +1,152 @@
+extern crate log;
+
+use crate::*;
+
+use llama_cpp_2::{
+    context::{LlamaContext, params::LlamaContextParams},
+    llama_batch::LlamaBatch,
+};
+
+impl DeepThoughtKvCache {
+    //
+    // Creates the context of owner with its LoRA adapters applied
+    //
+    pub fn new(
+        owner: &str,
+        backend: &LlamaBackend,
+        model: Arc<LlamaModel>,
+        params: LlamaContextParams,
+        adapters: &mut [DeepThoughtLoraAdapter],
+    ) -> Result<Self, Error> {
+        let context =
+            DeepThoughtLiveContext::try_new(model, |model| model.new_context(backend, params))?;
+        let mut cache = DeepThoughtKvCache {
+            owner: owner.to_string(),
+            tokens: Vec::new(),
+            lora_scales: DeepThoughtLoraAdapter::scales(adapters),
+            context,
+        };
+        cache.with_context(|context| DeepThoughtLoraAdapter::apply_all(adapters, context))?;
+        Ok(cache)
+    }
+
+    pub fn with_context<R>(&mut self, func: impl FnOnce(&mut LlamaContext) -> R) -> R {
+        self.context.with_dependent_mut(|_, context| func(context))
+    }
+
+    //
+    // Sets adapter scales changed since the context was last used, tokens
+    // decoded with other scales are dropped but the context is kept
+    //
+    pub fn sync_lora_scales(
+        &mut self,
+        adapters: &mut [DeepThoughtLoraAdapter],
+    ) -> Result<(), Error> {
+        let scales = DeepThoughtLoraAdapter::scales(adapters);
+        if scales == self.lora_scales {
+            return Ok(());
+        }
+        self.with_context(|context| DeepThoughtLoraAdapter::apply_all(adapters, context))?;
+        self.clear();
+        self.lora_scales = scales;
+        Ok(())
+    }
+
+    pub fn n_ctx(&mut self) -> usize {
+        self.with_context(|context| context.n_ctx() as usize)
+    }
+
+    //
+    // Number of leading tokens shared with the cached ones, always leaving
+    // at least one token to decode so the context produces fresh logits
+    //
+    pub fn common_prefix(&self, tokens: &[LlamaToken]) -> usize {
+        let common = self
+            .tokens
+            .iter()
+            .zip(tokens.iter())
+            .take_while(|(cached, token)| cached == token)
+            .count();
+        std::cmp::min(common, tokens.len().saturating_sub(1))
+    }
+
+    //
+    // Drops the cached tokens past the common prefix from the context and
+    // returns how many tokens no longer need decoding
+    //
+    pub fn trim(&mut self, tokens: &[LlamaToken]) -> usize {
+        let common = self.common_prefix(tokens);
+        if common < self.tokens.len() {
+            let trimmed = self.with_context(|context| {
+                context.clear_kv_cache_seq(Some(0), Some(common as u32), None)
+            });
+            match trimmed {
+                Ok(true) => {}
+                _ => {
+                    log::debug!("Failed to trim KV cache, decoding the whole prompt");
+                    self.clear();
+                    return 0;
+                }
+            }
+        }
+        self.tokens.truncate(common);
+        common
+    }
+
+    pub fn clear(&mut self) {
+        self.with_context(|context| context.clear_kv_cache());
+        self.tokens.clear();
+    }
+
+    //
+    // Copy of the context state, only needed to write sessions to disk
+    //
+    pub fn state(&mut self) -> Vec<u8> {
+        self.with_context(|context| {
+            let mut state = vec![0u8; context.get_state_size()];
+            let written = unsafe { context.copy_state_data(state.as_mut_ptr()) };
+            state.truncate(written);
+            state
+        })
+    }
+
+    //
+    // Loads a state written by state() for the tokens decoded into it
+    //
+    pub fn load_state(&mut self, state: &[u8], tokens: Vec<LlamaToken>) -> bool {
+        let read = self.with_context(|context| unsafe { context.set_state_data(state) });
+        if read == 0 {
+            self.clear();
+            return false;
+        }
+        self.tokens = tokens;
+        true
+    }
+
+    //
+    // Decodes tokens[start..] in batch_size chunks, logits only for the last one
+    //
+    pub fn decode_tokens(
+        context: &mut LlamaContext,
+        batch: &mut LlamaBatch,
+        tokens: &[LlamaToken],
+        start: usize,
+        batch_size: usize,
+    ) -> Result<(), Error> {
+        if tokens.is_empty() {
+            return Err(Error::InternalNativeError("Empty prompt".to_string()));
+        }
+        let last_index = tokens.len() - 1;
+        let mut pos = start;
+        while pos < tokens.len() {
+            let end = std::cmp::min(pos + batch_size, tokens.len());
+            batch.clear();
+            for i in pos..end {
+                batch.add(tokens[i], i as i32, &[0], i == last_index)?;
+            }
+            context.decode(batch)?;
+            pos = end;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+extern crate log;
+
+use crate::*;
+
+use llama_cpp_2::context::LlamaContext;
+
+impl DeepThoughtLoraAdapter {
+    pub fn load(model: &LlamaModel, path: &str, scale: f32) -> Result<Self, Error> {
+        let adapter = model.lora_adapter_init(path)?;
+        Ok(DeepThoughtLoraAdapter {
+            path: path.to_string(),
+            scale,
+            adapter,
+        })
+    }
+
+    pub fn scales(adapters: &[DeepThoughtLoraAdapter]) -> Vec<f32> {
+        adapters.iter().map(|lora| lora.scale).collect()
+    }
+
+    //
+    // Adapters are attached per context, so every new context has to get them again
+    //
+    pub fn apply_all(
+        adapters: &mut [DeepThoughtLoraAdapter],
+        context: &mut LlamaContext,
+    ) -> Result<(), Error> {
+        for lora in adapters.iter_mut() {
+            context.lora_adapter_set(&mut lora.adapter, lora.scale)?;
+        }
+        Ok(())
+    }
+}
+
+impl DeepThoughtModel {
+    //
+    // Adding or removing adapters needs a new context, a new id drops it.
+    // Scale changes are applied to the kept context on the next turn.
+    //
+    fn lora_adapters_changed(&mut self) {
+        self.id = nanoid::nanoid!();
+        self.kv_cache = None;
+    }
+
+    pub fn add_lora_adapter(&mut self, path: &str, scale: f32) -> Result<(), Error> {
+        let lora = DeepThoughtLoraAdapter::load(&self.model, path, scale)?;
+        self.lora_adapters.push(lora);
+        self.lora_adapters_changed();
+        Ok(())
+    }
+
+    //
+    // Scale 0.0 keeps the adapter loaded but disables it
+    //
+    pub fn set_lora_scale(&mut self, path: &str, scale: f32) -> bool {
+        match self.lora_adapters.iter_mut().find(|lora| lora.path == path) {
+            Some(lora) => {
+                lora.scale = scale;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_lora_adapter(&mut self, path: &str) -> bool {
+        let before = self.lora_adapters.len();
+        self.lora_adapters.retain(|lora| lora.path != path);
+        if self.lora_adapters.len() == before {
+            return false;
+        }
+        self.lora_adapters_changed();
+        true
+    }
+
+    pub fn clear_lora_adapters(&mut self) {
+        self.lora_adapters.clear();
+        self.lora_adapters_changed();
+    }
+
+    pub fn list_lora_adapters(&self) -> Vec<(String, f32)> {
+        self.lora_adapters
+            .iter()
+            .map(|lora| (lora.path.clone(), lora.scale))
+            .collect()
+    }
+}
+
+impl DeepThoughtCtxModel {
+    //
+    // Contexts cache KV state per model id, a new id drops it when adapters are
+    // added or removed. Scale changes are applied to the kept context instead.
+    //
+    fn lora_adapters_changed(&mut self) {
+        self.id = nanoid::nanoid!();
+    }
+
+    pub fn add_lora_adapter(&mut self, path: &str, scale: f32) -> Result<(), Error> {
+        let lora = DeepThoughtLoraAdapter::load(&self.model, path, scale)?;
+        self.lora_adapters.push(lora);
+        self.lora_adapters_changed();
+        Ok(())
+    }
+
+    pub fn set_lora_scale(&mut self, path: &str, scale: f32) -> bool {
+        match self.lora_adapters.iter_mut().find(|lora| lora.path == path) {
+            Some(lora) => {
+                lora.scale = scale;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_lora_adapter(&mut self, path: &str) -> bool {
+        let before = self.lora_adapters.len();
+        self.lora_adapters.retain(|lora| lora.path != path);
+        if self.lora_adapters.len() == before {
+            return false;
+        }
+        self.lora_adapters_changed();
+        true
+    }
+
+    pub fn clear_lora_adapters(&mut self) {
+        self.lora_adapters.clear();
+        self.lora_adapters_changed();
+    }
+
+    pub fn list_lora_adapters(&self) -> Vec<(String, f32)> {
+        self.lora_adapters
+            .iter()
+            .map(|lora| (lora.path.clone(), lora.scale))
+            .collect()
+    }
+}
@@ -0,0 +1,103 @@
+extern crate log;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::*;
+
+impl DeepThoughtMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        DeepThoughtMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            pinned: false,
+            timestamp: DeepThoughtMessage::now(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    //
+    // Seconds since the Unix epoch
+    //
+    pub fn now() -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs(),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn pinned(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+
+    //
+    // System and pinned messages are never dropped from the history
+    //
+    pub fn is_droppable(&self) -> bool {
+        !self.pinned && self.role != "system"
+    }
+
+    pub fn system(content: &str) -> Self {
+        DeepThoughtMessage::new("system", content)
+    }
+
+    pub fn user(content: &str) -> Self {
+        DeepThoughtMessage::new("user", content)
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        DeepThoughtMessage::new("assistant", content)
+    }
+
+    pub fn to_llama(&self) -> Result<LlamaChatMessage, Error> {
+        Ok(LlamaChatMessage::new(
+            self.role.clone(),
+            self.content.clone(),
+        )?)
+    }
+
+    pub fn to_llama_messages(
+        messages: &[DeepThoughtMessage],
+    ) -> Result<Vec<LlamaChatMessage>, Error> {
+        let mut res = Vec::with_capacity(messages.len());
+        for msg in messages.iter() {
+            res.push(msg.to_llama()?);
+        }
+        Ok(res)
+    }
+
+    pub fn to_openai(&self) -> serde_json::Value {
+        serde_json::json!({
+            "role": self.role,
+            "content": self.content,
+        })
+    }
+
+    //
+    // Accepts both plain string content and the list-of-parts form,
+    // text parts are concatenated and other parts are skipped
+    //
+    pub fn from_openai(value: &serde_json::Value) -> Result<Self, Error> {
+        let role = match value.get("role").and_then(|role| role.as_str()) {
+            Some("developer") => "system",
+            Some(role) => role,
+            None => return Err("OpenAI message without role".to_string().into()),
+        };
+        let content = match value.get("content") {
+            Some(serde_json::Value::String(content)) => content.clone(),
+            Some(serde_json::Value::Array(parts)) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(|text| text.as_str()))
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(_) => return Err("Unsupported OpenAI message content".to_string().into()),
+        };
+        Ok(DeepThoughtMessage::new(role, &content))
+    }
+}
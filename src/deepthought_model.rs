@@ -2,16 +2,16 @@ extern crate log;
 
 use crate::*;
 
-use std::num::{NonZero, NonZeroU32};
+use std::num::NonZeroU32;
 
 // use easy_error::bail;
+use serde::de::DeserializeOwned;
 use std::io::Write;
 
 use llama_cpp_2::{
     context::params::LlamaContextParams,
     llama_batch::LlamaBatch,
-    model::{AddBos, LlamaChatMessage, LlamaChatTemplate, Special},
-    sampling::LlamaSampler,
+    model::{AddBos, LlamaChatMessage, LlamaChatTemplate},
 };
 
 impl DeepThoughtModel {
@@ -21,18 +21,43 @@ impl DeepThoughtModel {
             Some(system_prompt) => system_prompt.to_string(),
             None => self.system_prompt.clone(),
         };
-        self.messages.push(LlamaChatMessage::new(
-            "system".to_string(),
-            system_prompt.to_string(),
-        )?);
+        self.messages
+            .push(DeepThoughtMessage::system(&system_prompt));
         Ok(())
     }
 
+    pub fn set_token_budget(&mut self, budget: Option<DeepThoughtTokenBudget>) {
+        self.token_budget = budget;
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        self.kv_cache = None;
+    }
+
+    //
+    // Context kept from the previous turn, a new one when there is none or
+    // it was created for other adapters
+    //
+    pub(crate) fn take_kv_cache(&mut self) -> Result<DeepThoughtKvCache, Error> {
+        let mut cache = match self.kv_cache.take() {
+            Some(cache) if cache.owner == self.id => cache,
+            _ => {
+                let context_params = self.context_params();
+                DeepThoughtKvCache::new(
+                    &self.id,
+                    &self.registry.backend,
+                    self.model.clone(),
+                    context_params,
+                    &mut self.lora_adapters,
+                )?
+            }
+        };
+        cache.sync_lora_scales(&mut self.lora_adapters)?;
+        Ok(cache)
+    }
+
     pub fn add_inference_to_prompt(&mut self, data: &str) -> Result<(), Error> {
-        self.messages.push(LlamaChatMessage::new(
-            "assistant".to_string(),
-            data.to_string(),
-        )?);
+        self.messages.push(DeepThoughtMessage::assistant(data));
         Ok(())
     }
 
@@ -40,36 +65,55 @@ impl DeepThoughtModel {
         &mut self,
         prompt: &str,
         output: &mut impl Write,
+    ) -> Result<(), Error> {
+        let params = self.sampling.clone();
+        self.send_with_history_and_params(prompt, output, &params)
+    }
+
+    pub fn send_with_history_and_params(
+        &mut self,
+        prompt: &str,
+        output: &mut impl Write,
+        params: &SamplingParams,
     ) -> Result<(), Error> {
         let mut inference = vec![];
-        self.infer(prompt, &mut inference, true)?;
+        self.infer(prompt, &mut inference, params)?;
         let inference = match String::from_utf8(inference) {
             Ok(inference) => inference,
             Err(err) => return Err(format!("{}", err).into()),
         };
 
-        self.messages.push(LlamaChatMessage::new(
-            "user".to_string(),
-            prompt.to_string(),
-        )?);
-
-        self.messages.push(LlamaChatMessage::new(
-            "assistant".to_string(),
-            inference.to_string(),
-        )?);
+        self.record_exchange(prompt, &inference)?;
 
         output.write_all(inference.as_bytes())?;
 
         Ok(())
     }
 
+    fn record_exchange(&mut self, prompt: &str, inference: &str) -> Result<(), Error> {
+        self.messages.push(DeepThoughtMessage::user(prompt));
+        self.messages.push(DeepThoughtMessage::assistant(inference));
+
+        Ok(())
+    }
+
     pub fn send_without_history(
         &mut self,
         prompt: &str,
         output: &mut impl Write,
+    ) -> Result<(), Error> {
+        let params = self.sampling.clone();
+        self.send_without_history_and_params(prompt, output, &params)
+    }
+
+    pub fn send_without_history_and_params(
+        &mut self,
+        prompt: &str,
+        output: &mut impl Write,
+        params: &SamplingParams,
     ) -> Result<(), Error> {
         let mut inference = vec![];
-        self.infer(prompt, &mut inference, false)?;
+        self.infer(prompt, &mut inference, params)?;
         let inference = match String::from_utf8(inference) {
             Ok(inference) => inference,
             Err(err) => return Err(format!("{}", err).into()),
@@ -80,159 +124,118 @@ impl DeepThoughtModel {
         Ok(())
     }
 
-    fn infer(&mut self, prompt: &str, output: &mut impl Write, history: bool) -> Result<(), Error> {
-        let chat_template = match self.chat_template {
-            Some(ref template) => template.clone(),
+    pub fn active_chat_template(&self) -> Result<LlamaChatTemplate, Error> {
+        match self.chat_template {
+            Some(ref template) => Ok(template.clone()),
             None => match LlamaChatTemplate::new("chatml") {
-                Ok(template) => template,
-                Err(err) => return Err(format!("{}", err).into()),
+                Ok(template) => Ok(template),
+                Err(err) => Err(format!("{}", err).into()),
             },
-        };
-        if history {
-            self.messages.push(LlamaChatMessage::new(
-                "user".to_string(),
-                prompt.to_string(),
-            )?);
         }
+    }
+
+    pub fn context_params(&self) -> LlamaContextParams {
+        self.context_options.apply(
+            LlamaContextParams::default()
+                .with_n_batch(self.batch_size as u32)
+                .with_n_ctx(NonZeroU32::new(self.context_length as u32)),
+        )
+    }
+
+    fn infer(
+        &mut self,
+        prompt: &str,
+        output: &mut impl Write,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtFinishReason, Error> {
+        let chat_template = self.active_chat_template()?;
+        match self.compact_history() {
+            Ok(_) => {}
+            Err(err) => return Err(format!("Error compacting history: {}", err).into()),
+        }
+        match self.token_budget {
+            Some(budget) => {
+                let mut history = self.messages.clone();
+                history.push(DeepThoughtMessage::user(prompt));
+                budget.fit(&mut history, self.context_length, |messages| {
+                    self.count_chat_tokens(messages)
+                })?;
+                let _ = history.pop();
+                self.messages = history;
+            }
+            None => {}
+        }
+        let mut messages = DeepThoughtMessage::to_llama_messages(&self.messages)?;
+        messages.push(LlamaChatMessage::new(
+            "user".to_string(),
+            prompt.to_string(),
+        )?);
 
         let prompt = self
             .model
-            .apply_chat_template(&chat_template, &self.messages, true)?;
+            .apply_chat_template(&chat_template, &messages, true)?;
         let tokens = self.model.str_to_token(&prompt, AddBos::Always)?;
 
-        let context_params = LlamaContextParams::default()
-            .with_n_batch(self.batch_size as u32)
-            .with_n_ctx(NonZeroU32::new(self.context_length as u32));
-        let mut context = self
-            .model
-            .new_context(&self.registry.backend, context_params)?;
+        let mut cache = self.take_kv_cache()?;
 
         let n_len = self.context_length as i32;
-        let n_cxt = context.n_ctx() as i32;
-        let n_kv_req = tokens.len() as i32 + (n_len - tokens.len() as i32);
-        if n_kv_req > n_cxt {
-            return Err(
-                Error::InternalNativeError(
-                    "n_kv_req > n_ctx, the required kv cache size is not big enough either reduce n_len or increase n_ctx".to_string()));
+        let n_ctx = cache.n_ctx();
+        if tokens.len() >= n_ctx {
+            self.kv_cache = Some(cache);
+            return Err(Error::ContextSize {
+                maximum: n_ctx,
+                actual: tokens.len(),
+            });
         }
 
         let mut batch = LlamaBatch::new(self.batch_size, 1);
 
-        let last_index = tokens.len() - 1;
-        for (i, token) in tokens.into_iter().enumerate() {
-            batch.add(token, i as i32, &[0], i == last_index)?;
-        }
-        context.decode(&mut batch)?;
-
-        // Decode and sample tokens.
-        let mut n_cur = batch.n_tokens();
-        let mut sampler = LlamaSampler::chain_simple([
-            LlamaSampler::min_p(0.05, 1),
-            LlamaSampler::temp(0.8),
-            LlamaSampler::dist(1337),
-        ]);
-        while n_cur <= n_len {
-            let token = sampler.sample(&context, batch.n_tokens() - 1);
-            sampler.accept(token);
-
-            if self.model.is_eog_token(token) {
-                eprintln!();
-                break;
-            }
-
-            let output_bytes = self.model.token_to_bytes(token, Special::Tokenize)?;
-            output.write_all(&output_bytes)?;
-            output.flush()?;
-
-            batch.clear();
-            batch.add(token, n_cur, &[0], true)?;
-
-            n_cur += 1;
-
-            match context.decode(&mut batch) {
-                Ok(_) => {}
-                Err(_) => return Err(Error::InternalNativeError("Decoding error".to_string())),
-            };
-        }
-
-        let _ = self.messages.pop();
-
-        Ok(())
+        let n_past = cache.trim(&tokens);
+        let mut tokens = tokens;
+        // Decode and sample tokens, a failed turn leaves the context in an
+        // unknown state so the cache is dropped
+        let finish_reason = cache.with_context(|context| {
+            DeepThoughtKvCache::decode_tokens(
+                context,
+                &mut batch,
+                &tokens,
+                n_past,
+                self.batch_size,
+            )?;
+            params.generate(&self.model, context, &mut batch, &mut tokens, n_len, output)
+        })?;
+        cache.tokens = tokens;
+        self.kv_cache = Some(cache);
+        Ok(finish_reason)
     }
 
-    pub fn embed(&self, text: &[impl AsRef<str>]) -> Result<Vec<Vec<f32>>, Error> {
-        // Tokenize the text.
-        let mut tokens = Vec::with_capacity(text.len());
-        for text in text {
-            tokens.push(self.model.str_to_token(text.as_ref(), AddBos::Always)?);
-        }
-
-        // Prepare inference context.
-        let thread_count = std::thread::available_parallelism()
-            .unwrap_or(NonZero::new(1).unwrap())
-            .get() as i32;
-        let context_params = LlamaContextParams::default()
-            .with_n_batch(self.context_length as u32)
-            .with_n_ubatch(self.context_length as u32)
-            .with_n_ctx(NonZeroU32::new(self.context_length as u32))
-            .with_n_threads(thread_count)
-            .with_n_threads_batch(thread_count)
-            .with_embeddings(true);
-        let mut context = self
-            .model
-            .new_context(&self.registry.backend, context_params)?;
-
-        // Make sure the KV cache is big enough to hold all the text.
-        let n_ctx = context.n_ctx() as usize;
-        let n_ubatch = context.n_ubatch() as usize;
-        for tokens in &tokens {
-            if n_ctx < tokens.len() {
-                return Err(Error::ContextSize {
-                    maximum: n_ubatch,
-                    actual: tokens.len(),
-                });
-            } else if n_ubatch < tokens.len() {
-                return Err(Error::MicrobatchSize {
-                    maximum: n_ubatch,
-                    actual: tokens.len(),
-                });
+    pub fn chat(&mut self, prompt: &str) -> Result<String, easy_error::Error> {
+        let mut output = vec![];
+        match self.send_with_history(prompt, &mut output) {
+            Ok(_) => {
+                return Ok(String::from_utf8_lossy(&output).to_string());
             }
+            Err(err) => easy_error::bail!("{:?}", err),
         }
+    }
 
-        // Prepare a reusable batch.
-        let mut batch = LlamaBatch::new(n_ctx, 1);
-
-        // TODO: @caer: include multiple tokens per batch if possible.
-        // Embed batches.
-        let mut embeddings = Vec::with_capacity(tokens.len());
-        for tokens in tokens {
-            batch.add_sequence(&tokens, 0, false)?;
-
-            // Run inference for embedding.
-            context.clear_kv_cache();
-            context.decode(&mut batch)?;
-
-            // Extract embedding from the model.
-            let embedding = context.embeddings_seq_ith(0)?;
-
-            // Normalize embedding.
-            let embedding_magnitude = embedding
-                .iter()
-                .fold(0.0, |acc, &val| val.mul_add(val, acc))
-                .sqrt();
-            let embedding: Vec<_> = embedding
-                .iter()
-                .map(|&val| val / embedding_magnitude)
-                .collect();
-
-            embeddings.push(embedding);
+    pub fn ask(&mut self, prompt: &str) -> Result<String, easy_error::Error> {
+        let mut output = vec![];
+        match self.send_without_history(prompt, &mut output) {
+            Ok(_) => {
+                return Ok(String::from_utf8_lossy(&output).to_string());
+            }
+            Err(err) => easy_error::bail!("{:?}", err),
         }
-        Ok(embeddings)
     }
 
-    pub fn chat(&mut self, prompt: &str) -> Result<String, easy_error::Error> {
+    pub fn chat_with_params(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<String, easy_error::Error> {
         let mut output = vec![];
-        match self.send_with_history(prompt, &mut output) {
+        match self.send_with_history_and_params(prompt, &mut output, params) {
             Ok(_) => {
                 return Ok(String::from_utf8_lossy(&output).to_string());
             }
@@ -240,13 +243,107 @@ impl DeepThoughtModel {
         }
     }
 
-    pub fn ask(&mut self, prompt: &str) -> Result<String, easy_error::Error> {
+    pub fn ask_with_params(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<String, easy_error::Error> {
         let mut output = vec![];
-        match self.send_without_history(prompt, &mut output) {
+        match self.send_without_history_and_params(prompt, &mut output, params) {
             Ok(_) => {
                 return Ok(String::from_utf8_lossy(&output).to_string());
             }
             Err(err) => easy_error::bail!("{:?}", err),
         }
     }
+
+    pub fn chat_stream(
+        &mut self,
+        prompt: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        let params = self.sampling.clone();
+        let mut stream = DeepThoughtStream::new(callback);
+        match self.infer(prompt, &mut stream, &params) {
+            Ok(_) => {}
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+        let inference = stream.finish();
+        match self.record_exchange(prompt, &inference) {
+            Ok(_) => Ok(inference),
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    pub fn ask_stream(
+        &mut self,
+        prompt: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        let params = self.sampling.clone();
+        let mut stream = DeepThoughtStream::new(callback);
+        match self.infer(prompt, &mut stream, &params) {
+            Ok(_) => Ok(stream.finish()),
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    pub fn chat_completion(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtCompletion, easy_error::Error> {
+        let mut output = vec![];
+        let finish_reason = match self.infer(prompt, &mut output, params) {
+            Ok(finish_reason) => finish_reason,
+            Err(err) => easy_error::bail!("{:?}", err),
+        };
+        let text = String::from_utf8_lossy(&output).to_string();
+        match self.record_exchange(prompt, &text) {
+            Ok(_) => Ok(DeepThoughtCompletion {
+                text,
+                finish_reason,
+            }),
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    pub fn ask_completion(
+        &mut self,
+        prompt: &str,
+        params: &SamplingParams,
+    ) -> Result<DeepThoughtCompletion, easy_error::Error> {
+        let mut output = vec![];
+        match self.infer(prompt, &mut output, params) {
+            Ok(finish_reason) => Ok(DeepThoughtCompletion {
+                text: String::from_utf8_lossy(&output).to_string(),
+                finish_reason,
+            }),
+            Err(err) => easy_error::bail!("{:?}", err),
+        }
+    }
+
+    //
+    // Asks with the output constrained by a grammar built from the JSON Schema
+    //
+    pub fn ask_json_schema<T: DeserializeOwned>(
+        &mut self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<T, easy_error::Error> {
+        let grammar = DeepThoughtGrammar::from_json_schema(schema)?;
+        let params = self.sampling.clone().grammar(&grammar);
+        let result = self.ask_with_params(prompt, &params)?;
+        match serde_json::from_str(&result) {
+            Ok(value) => Ok(value),
+            Err(err) => easy_error::bail!("Error parsing JSON output: {}", err),
+        }
+    }
+
+    pub fn ask_json<T: DeserializeOwned + DeepThoughtJsonSchema>(
+        &mut self,
+        prompt: &str,
+    ) -> Result<T, easy_error::Error> {
+        self.ask_json_schema(prompt, &T::json_schema())
+    }
 }
@@ -0,0 +1,219 @@
+extern crate log;
+
+use crate::*;
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::pin::Pin;
+
+use llama_cpp_2::{
+    context::params::{KvCacheType, LlamaContextParams, RopeScalingType},
+    model::params::{LlamaModelParams, kv_overrides::ParamOverrideValue},
+};
+
+//
+// llama.cpp keeps string overrides in a fixed 128 byte buffer
+//
+const KV_OVERRIDE_STR_LEN: usize = 128;
+
+impl DeepThoughtKvOverride {
+    fn to_llama(&self) -> Result<ParamOverrideValue, Error> {
+        match self {
+            DeepThoughtKvOverride::Bool(value) => Ok(ParamOverrideValue::Bool(*value)),
+            DeepThoughtKvOverride::Int(value) => Ok(ParamOverrideValue::Int(*value)),
+            DeepThoughtKvOverride::Float(value) => Ok(ParamOverrideValue::Float(*value)),
+            DeepThoughtKvOverride::Str(value) => {
+                if value.len() >= KV_OVERRIDE_STR_LEN {
+                    return Err(format!(
+                        "KV override value is longer than {} bytes",
+                        KV_OVERRIDE_STR_LEN - 1
+                    )
+                    .into());
+                }
+                let mut buf: [c_char; KV_OVERRIDE_STR_LEN] = [0; KV_OVERRIDE_STR_LEN];
+                for (n, b) in value.bytes().enumerate() {
+                    buf[n] = b as c_char;
+                }
+                Ok(ParamOverrideValue::Str(buf))
+            }
+        }
+    }
+}
+
+impl DeepThoughtModelParams {
+    pub fn new() -> Self {
+        DeepThoughtModelParams::default()
+    }
+
+    pub fn use_mmap(mut self, use_mmap: bool) -> Self {
+        self.use_mmap = Some(use_mmap);
+        self
+    }
+
+    //
+    // Check DeepThoughtBackend::supports_mlock() before enabling
+    //
+    pub fn use_mlock(mut self, use_mlock: bool) -> Self {
+        self.use_mlock = Some(use_mlock);
+        self
+    }
+
+    pub fn vocab_only(mut self, vocab_only: bool) -> Self {
+        self.vocab_only = Some(vocab_only);
+        self
+    }
+
+    pub fn kv_override(mut self, key: &str, value: DeepThoughtKvOverride) -> Self {
+        self.kv_overrides.push((key.to_string(), value));
+        self
+    }
+
+    //
+    // Models loaded with equal params share a registry entry
+    //
+    pub fn key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    pub fn to_llama(&self) -> Result<Pin<Box<LlamaModelParams>>, Error> {
+        let mut params = LlamaModelParams::default();
+        match self.use_mmap {
+            Some(use_mmap) => params = params.with_use_mmap(use_mmap),
+            None => {}
+        }
+        match self.use_mlock {
+            Some(use_mlock) => params = params.with_use_mlock(use_mlock),
+            None => {}
+        }
+        match self.vocab_only {
+            Some(vocab_only) => params = params.with_vocab_only(vocab_only),
+            None => {}
+        }
+        // KV overrides are referenced by pointer from the params, so they must not move
+        let mut params = Box::pin(params);
+        for (key, value) in self.kv_overrides.iter() {
+            let c_key = match CString::new(key.as_str()) {
+                Ok(c_key) => c_key,
+                Err(err) => return Err(format!("Invalid KV override key {}: {}", key, err).into()),
+            };
+            params
+                .as_mut()
+                .append_kv_override(&c_key, value.to_llama()?);
+        }
+        Ok(params)
+    }
+}
+
+impl DeepThoughtKvCacheType {
+    fn to_llama(&self) -> KvCacheType {
+        match self {
+            DeepThoughtKvCacheType::F32 => KvCacheType::F32,
+            DeepThoughtKvCacheType::F16 => KvCacheType::F16,
+            DeepThoughtKvCacheType::BF16 => KvCacheType::BF16,
+            DeepThoughtKvCacheType::Q8_0 => KvCacheType::Q8_0,
+            DeepThoughtKvCacheType::Q5_1 => KvCacheType::Q5_1,
+            DeepThoughtKvCacheType::Q5_0 => KvCacheType::Q5_0,
+            DeepThoughtKvCacheType::Q4_1 => KvCacheType::Q4_1,
+            DeepThoughtKvCacheType::Q4_0 => KvCacheType::Q4_0,
+        }
+    }
+}
+
+impl DeepThoughtRopeScaling {
+    fn to_llama(&self) -> RopeScalingType {
+        match self {
+            DeepThoughtRopeScaling::None => RopeScalingType::None,
+            DeepThoughtRopeScaling::Linear => RopeScalingType::Linear,
+            DeepThoughtRopeScaling::Yarn => RopeScalingType::Yarn,
+        }
+    }
+}
+
+impl DeepThoughtContextParams {
+    pub fn new() -> Self {
+        DeepThoughtContextParams::default()
+    }
+
+    pub fn n_threads(mut self, n_threads: i32) -> Self {
+        self.n_threads = Some(n_threads);
+        self
+    }
+
+    pub fn n_threads_batch(mut self, n_threads_batch: i32) -> Self {
+        self.n_threads_batch = Some(n_threads_batch);
+        self
+    }
+
+    pub fn flash_attention(mut self, flash_attention: bool) -> Self {
+        self.flash_attention = Some(flash_attention);
+        self
+    }
+
+    //
+    // Quantized KV cache types usually need flash attention enabled
+    //
+    pub fn kv_cache_type(
+        mut self,
+        type_k: DeepThoughtKvCacheType,
+        type_v: DeepThoughtKvCacheType,
+    ) -> Self {
+        self.type_k = Some(type_k);
+        self.type_v = Some(type_v);
+        self
+    }
+
+    pub fn rope_scaling(mut self, rope_scaling: DeepThoughtRopeScaling) -> Self {
+        self.rope_scaling = Some(rope_scaling);
+        self
+    }
+
+    pub fn rope_freq_base(mut self, rope_freq_base: f32) -> Self {
+        self.rope_freq_base = Some(rope_freq_base);
+        self
+    }
+
+    pub fn rope_freq_scale(mut self, rope_freq_scale: f32) -> Self {
+        self.rope_freq_scale = Some(rope_freq_scale);
+        self
+    }
+
+    //
+    // Overrides the options that are set on top of the given params
+    //
+    pub fn apply(&self, params: LlamaContextParams) -> LlamaContextParams {
+        let mut params = params;
+        match self.n_threads {
+            Some(n_threads) => params = params.with_n_threads(n_threads),
+            None => {}
+        }
+        match self.n_threads_batch {
+            Some(n_threads_batch) => params = params.with_n_threads_batch(n_threads_batch),
+            None => {}
+        }
+        match self.flash_attention {
+            Some(flash_attention) => params = params.with_flash_attention(flash_attention),
+            None => {}
+        }
+        match self.type_k {
+            Some(type_k) => params = params.with_type_k(type_k.to_llama()),
+            None => {}
+        }
+        match self.type_v {
+            Some(type_v) => params = params.with_type_v(type_v.to_llama()),
+            None => {}
+        }
+        match self.rope_scaling {
+            Some(rope_scaling) => params = params.with_rope_scaling_type(rope_scaling.to_llama()),
+            None => {}
+        }
+        match self.rope_freq_base {
+            Some(rope_freq_base) => params = params.with_rope_freq_base(rope_freq_base),
+            None => {}
+        }
+        match self.rope_freq_scale {
+            Some(rope_freq_scale) => params = params.with_rope_freq_scale(rope_freq_scale),
+            None => {}
+        }
+        params
+    }
+}
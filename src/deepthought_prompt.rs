@@ -9,3 +9,45 @@ impl DeepThoughtRecommededPrompt {
         self.prompts.get(query).unwrap_or(&self.raw_prompt)
     }
 }
+
+impl DeepThoughtJsonSchema for DeepThoughtRecommededPrompt {
+    fn json_schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "raw_prompt": { "type": "string" },
+                "clarifying_questions": { "type": "array", "items": { "type": "string" } },
+                "prompts": {
+                    "type": "object",
+                    "properties": {
+                        "deterministic": { "type": "string" },
+                        "balanced": { "type": "string" },
+                        "creative": { "type": "string" }
+                    },
+                    "required": ["deterministic", "balanced", "creative"]
+                },
+                "rationale_bullets": { "type": "array", "items": { "type": "string" } },
+                "suggested_parameters": {
+                    "type": "object",
+                    "properties": {
+                        "temperature": { "type": "number" },
+                        "top_p": { "type": "number" },
+                        "max_tokens": { "type": "integer" },
+                        "stop": { "type": "array", "items": { "type": "string" } },
+                        "seed": { "type": "integer" }
+                    },
+                    "required": ["temperature", "top_p", "max_tokens", "stop", "seed"]
+                },
+                "quick_tests": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": [
+                "raw_prompt",
+                "clarifying_questions",
+                "prompts",
+                "rationale_bullets",
+                "suggested_parameters",
+                "quick_tests"
+            ]
+        })
+    }
+}
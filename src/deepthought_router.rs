@@ -18,6 +18,11 @@ impl DeepThoughtRouter {
             routes: HashMap::new(),
             ctx_routes: HashMap::new(),
             sessions: HashMap::new(),
+            session_store: None,
+            session_ttl: None,
+            embedding_dimension: None,
+            model_params: DeepThoughtModelParams::default(),
+            context_params: DeepThoughtContextParams::default(),
             facts: HashMap::new(),
             embed_model: None,
             prompt_model: None,
@@ -29,7 +34,18 @@ impl DeepThoughtRouter {
         })
     }
     pub fn embed_model(&mut self, gguf_model: &str) -> Result<(), easy_error::Error> {
-        let model = match self.backend.load_model(gguf_model, "You are the robot!") {
+        self.embed_model_with_params(gguf_model, &DeepThoughtModelParams::default())
+    }
+    pub fn embed_model_with_params(
+        &mut self,
+        gguf_model: &str,
+        model_params: &DeepThoughtModelParams,
+    ) -> Result<(), easy_error::Error> {
+        let model = match self.backend.load_model_with_params(
+            gguf_model,
+            "You are the robot!",
+            model_params,
+        ) {
             Ok(model) => model,
             Err(err) => {
                 easy_error::bail!("EMBED MODEL ERROR: {:?}", err);
@@ -38,4 +54,71 @@ impl DeepThoughtRouter {
         self.embed_model = Some(model);
         Ok(())
     }
+    //
+    // Loaded GGUF weights and how many routes, ctx routes and embedders hold them
+    //
+    pub fn loaded_models(&self) -> Result<Vec<(DeepThoughtModelKey, usize)>, easy_error::Error> {
+        match self.backend.loaded_models() {
+            Ok(models) => Ok(models),
+            Err(err) => bail!("MODEL REGISTRY ERROR: {:?}", err),
+        }
+    }
+    //
+    // Frees GGUF weights no route, ctx route or embedder holds anymore
+    //
+    pub fn unload_unused_models(&mut self) -> Result<usize, easy_error::Error> {
+        match self.backend.unload_unused_models() {
+            Ok(unloaded) => Ok(unloaded),
+            Err(err) => bail!("MODEL REGISTRY ERROR: {:?}", err),
+        }
+    }
+    //
+    // Checks that no model runs with a context longer than it was trained on
+    // and that embedders produce the expected embedding dimension
+    //
+    pub fn validate(&self) -> Result<(), easy_error::Error> {
+        match self.prompt_model {
+            Some(ref model) => model.info().check_context_length(model.context_length)?,
+            None => {}
+        }
+        for (name, route) in self.routes.iter() {
+            match route
+                .model
+                .info()
+                .check_context_length(route.model.context_length)
+            {
+                Ok(_) => {}
+                Err(err) => bail!("Route {}: {}", name, err),
+            }
+        }
+        for (name, ctx_route) in self.ctx_routes.iter() {
+            match ctx_route
+                .info()
+                .check_context_length(ctx_route.context_length)
+            {
+                Ok(_) => {}
+                Err(err) => bail!("Context route {}: {}", name, err),
+            }
+        }
+        let dimension = match self.embedding_dimension {
+            Some(dimension) => dimension,
+            None => return Ok(()),
+        };
+        match self.embed_model {
+            Some(ref embed_model) => embed_model.info().check_embedding_dimension(dimension)?,
+            None => {}
+        }
+        for (name, route) in self.routes.iter() {
+            match route.embed_model {
+                Some(ref embed_model) => {
+                    match embed_model.info().check_embedding_dimension(dimension) {
+                        Ok(_) => {}
+                        Err(err) => bail!("Route {}: {}", name, err),
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
 }
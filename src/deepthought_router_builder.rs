@@ -15,6 +15,12 @@ impl DeepThoughtRouterBuilder {
             query_preference: None,
             catalog_path: None,
             embedding_query_prefix: "".to_string(),
+            session_dir: None,
+            session_db: None,
+            session_ttl: None,
+            model_params: None,
+            context_params: None,
+            embedding_dimension: None,
         }
     }
 
@@ -43,6 +49,48 @@ impl DeepThoughtRouterBuilder {
         self
     }
 
+    //
+    // Keep sessions as JSON files in the directory
+    //
+    pub fn session_dir(mut self, session_dir: &str) -> Self {
+        self.session_dir = Some(session_dir.to_string());
+        self
+    }
+
+    //
+    // Keep sessions in an embedded key-value database
+    //
+    pub fn session_db(mut self, session_db: &str) -> Self {
+        self.session_db = Some(session_db.to_string());
+        self
+    }
+
+    pub fn session_ttl(mut self, session_ttl: std::time::Duration) -> Self {
+        self.session_ttl = Some(session_ttl);
+        self
+    }
+
+    //
+    // Used for the prompt model and the default embedding model
+    //
+    pub fn model_params(mut self, params: DeepThoughtModelParams) -> Self {
+        self.model_params = Some(params);
+        self
+    }
+
+    pub fn context_params(mut self, params: DeepThoughtContextParams) -> Self {
+        self.context_params = Some(params);
+        self
+    }
+
+    //
+    // Expected catalog embedding size, every embedder is checked against it
+    //
+    pub fn embedding_dimension(mut self, dimension: usize) -> Self {
+        self.embedding_dimension = Some(dimension);
+        self
+    }
+
     pub fn balanced_preference(mut self) -> Self {
         self.query_preference = Some("balanced".to_string());
         self
@@ -82,17 +130,53 @@ impl DeepThoughtRouterBuilder {
         };
         router.embedding_query_prefix = self.embedding_query_prefix;
         router.catalog = Some(catalog);
-        router.prompt_model = match router
-            .backend
-            .load_context_model(&prompt_model, &self.system_prompt)
-        {
-            Ok(model) => Some(model),
+        let model_params = match self.model_params {
+            Some(params) => params,
+            None => DeepThoughtModelParams::default(),
+        };
+        let context_params = match self.context_params {
+            Some(params) => params,
+            None => DeepThoughtContextParams::default(),
+        };
+        router.model_params = model_params.clone();
+        router.context_params = context_params.clone();
+        router.prompt_model = match router.backend.load_context_model_with_params(
+            &prompt_model,
+            &self.system_prompt,
+            &model_params,
+        ) {
+            Ok(mut model) => {
+                model.context_options = context_params.clone();
+                Some(model)
+            }
             Err(err) => bail!("Failed to load prompt model: {:?}", err),
         };
-        match router.embed_model(&default_embed_model) {
+        match router.embed_model_with_params(&default_embed_model, &model_params) {
             Ok(model) => Some(model),
             Err(err) => bail!("Failed to load default embed model: {}", err),
         };
+        match router.embed_model {
+            Some(ref mut embed_model) => embed_model.context_options = context_params,
+            None => {}
+        }
+        match (self.session_dir, self.session_db) {
+            (Some(_), Some(_)) => bail!("Only one of session_dir and session_db can be set"),
+            (Some(session_dir), None) => match DeepThoughtFileSessionStore::new(&session_dir) {
+                Ok(store) => router.set_session_store(Box::new(store)),
+                Err(err) => bail!("Failed to open session directory: {}", err),
+            },
+            (None, Some(session_db)) => match DeepThoughtSledSessionStore::new(&session_db) {
+                Ok(store) => router.set_session_store(Box::new(store)),
+                Err(err) => bail!("Failed to open session database: {}", err),
+            },
+            (None, None) => {}
+        }
+        router.session_ttl = self.session_ttl;
+        router.embedding_dimension = self.embedding_dimension;
+        match router.validate() {
+            Ok(_) => {}
+            Err(err) => bail!("Router validation failed: {}", err),
+        }
         router.query_preference = match self.query_preference {
             Some(preference) => preference,
             None => "balanced".to_string(),
@@ -49,6 +49,23 @@ impl DeepThoughtRouter {
         }
     }
     pub fn query_catalog(&mut self, q: &str) -> Result<Vec<VecStoreNeighbors>, easy_error::Error> {
+        self.query_catalog_with(q, None)
+    }
+    //
+    // Restricts catalog results by metadata, e.g. tag.type == "route"
+    //
+    pub fn query_catalog_filtered(
+        &mut self,
+        q: &str,
+        filter: &DeepThoughtFilter,
+    ) -> Result<Vec<VecStoreNeighbors>, easy_error::Error> {
+        self.query_catalog_with(q, Some(filter))
+    }
+    fn query_catalog_with(
+        &mut self,
+        q: &str,
+        filter: Option<&DeepThoughtFilter>,
+    ) -> Result<Vec<VecStoreNeighbors>, easy_error::Error> {
         let embedder = match &self.embed_model {
             Some(embed_model) => embed_model,
             None => bail!("Embedding model not set"),
@@ -59,23 +76,18 @@ impl DeepThoughtRouter {
             Err(err) => bail!("Error embedding query: {:?}", err),
         };
         let n_results: Vec<Neighbor> = match self.catalog {
-            Some(ref mut catalog) => match catalog.query_neighbors(vector[0].clone(), q) {
-                Ok(results) => results,
-                Err(err) => bail!("Error querying: {}", err),
-            },
+            Some(ref mut catalog) => {
+                let results = match filter {
+                    Some(filter) => catalog.query_neighbors_filtered(vector[0].clone(), q, filter),
+                    None => catalog.query_neighbors(vector[0].clone(), q),
+                };
+                match results {
+                    Ok(results) => results,
+                    Err(err) => bail!("Error querying: {}", err),
+                }
+            }
             None => bail!("Vector store not set"),
         };
-        let mut result: Vec<VecStoreNeighbors> = Vec::new();
-        for neighbor in n_results {
-            let metadata = neighbor.metadata.fields.clone();
-            let score = neighbor.score;
-            let id = neighbor.id.clone();
-            result.push(VecStoreNeighbors {
-                id,
-                score,
-                metadata,
-            });
-        }
-        Ok(result)
+        Ok(n_results.into_iter().map(VecStoreNeighbors::from).collect())
     }
 }
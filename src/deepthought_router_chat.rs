@@ -28,4 +28,113 @@ impl DeepThoughtRouter {
             Err(err) => bail!("{}", err),
         }
     }
+    //
+    // Chats with the route using the given LoRA scales for this request only,
+    // the previous scales are restored afterwards. Scales are applied to the
+    // route's kept context, repeating them reuses its KV cache.
+    //
+    pub fn chat_with_lora(
+        &mut self,
+        route_name: &str,
+        query: &str,
+        scales: &[(&str, f32)],
+    ) -> Result<String, easy_error::Error> {
+        let actual_prompt = match self.recommended_prompt(query) {
+            Ok(recommended_prompt) => recommended_prompt,
+            Err(err) => bail!("{}", err),
+        };
+        let model = match self.get_route(route_name) {
+            Some(model) => model,
+            None => bail!("Route {} not found", route_name),
+        };
+        let previous = model.model.list_lora_adapters();
+        for (path, _) in scales.iter() {
+            if !previous.iter().any(|(attached, _)| attached == path) {
+                bail!(
+                    "LoRA adapter {} is not attached to route {}",
+                    path,
+                    route_name
+                );
+            }
+        }
+        for (path, scale) in scales.iter() {
+            model.model.set_lora_scale(path, *scale);
+        }
+        let result = model.chat(&actual_prompt);
+        for (path, scale) in previous.iter() {
+            model.model.set_lora_scale(path, *scale);
+        }
+        match result {
+            Ok(result) => Ok(result),
+            Err(err) => bail!("{}", err),
+        }
+    }
+    pub fn chat_stream(
+        &mut self,
+        route_name: &str,
+        query: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        let actual_prompt = match self.recommended_prompt(query) {
+            Ok(recommended_prompt) => recommended_prompt,
+            Err(err) => bail!("{}", err),
+        };
+        let model = match self.get_route(route_name) {
+            Some(model) => model,
+            None => bail!("Route {} not found", route_name),
+        };
+        match model.chat_stream(&actual_prompt, callback) {
+            Ok(result) => Ok(result),
+            Err(err) => bail!("{}", err),
+        }
+    }
+    //
+    // Runs the ctx route model against the named session, so one loaded model
+    // serves many independent conversations
+    //
+    pub fn chat_session(
+        &mut self,
+        route_name: &str,
+        session_name: &str,
+        query: &str,
+    ) -> Result<String, easy_error::Error> {
+        self.chat_session_stream(route_name, session_name, query, |_| {})
+    }
+    pub fn chat_session_stream(
+        &mut self,
+        route_name: &str,
+        session_name: &str,
+        query: &str,
+        callback: impl FnMut(&str),
+    ) -> Result<String, easy_error::Error> {
+        let actual_prompt = match self.recommended_prompt(query) {
+            Ok(recommended_prompt) => recommended_prompt,
+            Err(err) => bail!("{}", err),
+        };
+        // Restores the session from the session store when needed
+        match self.get_session(session_name) {
+            Ok(_) => {}
+            Err(err) => bail!("Session {}: {}", session_name, err),
+        }
+        match self.compact_session_with_summarizer(session_name) {
+            Ok(_) => {}
+            Err(err) => bail!("Error compacting session {}: {}", session_name, err),
+        }
+        let context = match self.sessions.get_mut(session_name) {
+            Some(context) => context,
+            None => bail!("Session {} not found", session_name),
+        };
+        let model = match self.ctx_routes.get_mut(route_name) {
+            Some(model) => model,
+            None => bail!("Context route {} not found", route_name),
+        };
+        let result = match model.chat_stream(&actual_prompt, context, callback) {
+            Ok(result) => result,
+            Err(err) => bail!("{}", err),
+        };
+        match self.persist_session(session_name) {
+            Ok(_) => Ok(result),
+            Err(err) => bail!("Error persisting session {}: {}", session_name, err),
+        }
+    }
 }
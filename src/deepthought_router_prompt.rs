@@ -70,12 +70,8 @@ impl DeepThoughtRouter {
                     Ok(true_prompt) => true_prompt,
                     Err(err) => bail!("{}", err),
                 };
-                let result = match prompt_model.chat(&true_prompt, &mut ctx) {
-                    Ok(result) => result,
-                    Err(err) => bail!("{}", err),
-                };
                 let recommended_prompt: DeepThoughtRecommededPrompt =
-                    match serde_json::from_str(&result) {
+                    match prompt_model.chat_json(&true_prompt, &mut ctx) {
                         Ok(recommended_prompt) => recommended_prompt,
                         Err(err) => bail!("{}", err),
                     };
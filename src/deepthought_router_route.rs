@@ -29,6 +29,36 @@ impl DeepThoughtRouter {
         Ok(())
     }
 
+    //
+    // Ctx route on a base model with LoRA adapters, loaded with the router's
+    // model params so the base weights are shared with every other route
+    // loaded from the same GGUF file
+    //
+    pub fn new_lora_ctx_route(
+        &mut self,
+        name: &str,
+        gguf_model: &str,
+        system_prompt: &str,
+        adapters: &[(&str, f32)],
+    ) -> Result<(), easy_error::Error> {
+        let mut ctx_route = match self.backend.load_context_model_with_params(
+            gguf_model,
+            system_prompt,
+            &self.model_params,
+        ) {
+            Ok(ctx_route) => ctx_route,
+            Err(err) => bail!("ROUTE ERROR: {:?}", err),
+        };
+        ctx_route.context_options = self.context_params.clone();
+        for (path, scale) in adapters.iter() {
+            match ctx_route.add_lora_adapter(path, *scale) {
+                Ok(_) => {}
+                Err(err) => bail!("LORA ERROR {}: {:?}", path, err),
+            }
+        }
+        self.new_ctx_route(name, ctx_route)
+    }
+
     pub fn get_route(&mut self, name: &str) -> Option<&mut DeepThought> {
         self.routes.get_mut(name)
     }
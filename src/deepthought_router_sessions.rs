@@ -1,6 +1,7 @@
 extern crate log;
 
 use easy_error::bail;
+use std::time::Duration;
 
 use crate::*;
 
@@ -22,24 +23,235 @@ impl DeepThoughtRouter {
             }
         };
         self.sessions.insert(name.to_string(), new_context);
-        Ok(())
+        self.persist_session(name)
+    }
+    pub fn set_session_store(&mut self, store: Box<dyn SessionStore>) {
+        self.session_store = Some(store);
+    }
+    pub fn set_session_ttl(&mut self, ttl: Option<Duration>) {
+        self.session_ttl = ttl;
+    }
+    fn session_age(updated: u64) -> Duration {
+        Duration::from_secs(DeepThoughtMessage::now().saturating_sub(updated))
     }
+    fn is_expired(&self, updated: u64) -> bool {
+        match self.session_ttl {
+            Some(ttl) => DeepThoughtRouter::session_age(updated) > ttl,
+            None => false,
+        }
+    }
+    //
+    // Sessions missing in memory are restored from the session store,
+    // expired ones, in memory or in the store, are deleted instead
+    //
     pub fn get_session(
         &mut self,
         name: &str,
     ) -> Result<&mut DeepThoughtContext, easy_error::Error> {
+        if !self.sessions.contains_key(name) {
+            let record = match self.session_store {
+                Some(ref store) => store.load(name)?,
+                None => None,
+            };
+            match record {
+                Some(record) if self.is_expired(record.updated) => {
+                    let _ = self.drop_session(name);
+                    bail!("Session expired");
+                }
+                Some(record) => {
+                    self.sessions
+                        .insert(name.to_string(), DeepThoughtContext::from_record(record));
+                }
+                None => {}
+            }
+        }
+        let expired = match self.sessions.get(name) {
+            Some(context) => self.is_expired(context.updated()),
+            None => false,
+        };
+        if expired {
+            let _ = self.drop_session(name);
+            bail!("Session expired");
+        }
         match self.sessions.get_mut(name) {
             Some(context) => Ok(context),
             None => bail!("Session not found"),
         }
     }
+    //
+    // Writes the session to the session store, does nothing without one
+    //
+    pub fn persist_session(&mut self, name: &str) -> Result<(), easy_error::Error> {
+        let store = match self.session_store {
+            Some(ref store) => store,
+            None => return Ok(()),
+        };
+        match self.sessions.get(name) {
+            Some(context) => store.save(name, &context.to_record()),
+            None => bail!("Session not found"),
+        }
+    }
+    pub fn persist_sessions(&mut self) -> Result<(), easy_error::Error> {
+        let names: Vec<String> = self.sessions.keys().cloned().collect();
+        for name in names.iter() {
+            self.persist_session(name)?;
+        }
+        Ok(())
+    }
     pub fn drop_session(&mut self, name: &str) -> Result<(), easy_error::Error> {
-        match self.sessions.remove(name) {
-            Some(_) => Ok(()),
+        let in_memory = self.sessions.remove(name).is_some();
+        let in_store = match self.session_store {
+            Some(ref store) => store.delete(name)?,
+            None => false,
+        };
+        if in_memory || in_store {
+            Ok(())
+        } else {
+            bail!("Session not found")
+        }
+    }
+    pub fn export_session(&mut self, name: &str, path: &str) -> Result<(), easy_error::Error> {
+        match self.sessions.get(name) {
+            Some(context) => context.save(path),
             None => bail!("Session not found"),
         }
     }
+    pub fn import_session(&mut self, name: &str, path: &str) -> Result<(), easy_error::Error> {
+        let context = DeepThoughtContext::load(path)?;
+        self.sessions.insert(name.to_string(), context);
+        Ok(())
+    }
+    //
+    // Last update time of every known session, in memory and in the store
+    //
+    fn session_updates(&self) -> Result<HashMap<String, u64>, easy_error::Error> {
+        let mut res: HashMap<String, u64> = HashMap::new();
+        match self.session_store {
+            Some(ref store) => {
+                for name in store.names()?.iter() {
+                    match store.load(name)? {
+                        Some(record) => {
+                            res.insert(name.clone(), record.updated);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            None => {}
+        }
+        for (name, context) in self.sessions.iter() {
+            res.insert(name.clone(), context.updated());
+        }
+        Ok(res)
+    }
     pub fn list_sessions(&mut self) -> Vec<String> {
-        self.sessions.keys().cloned().collect()
+        match self.session_updates() {
+            Ok(updates) => {
+                let mut res: Vec<String> = updates
+                    .into_iter()
+                    .filter(|(_, updated)| !self.is_expired(*updated))
+                    .map(|(name, _)| name)
+                    .collect();
+                res.sort();
+                res
+            }
+            Err(err) => {
+                log::error!("Error listing stored sessions: {}", err);
+                self.sessions.keys().cloned().collect()
+            }
+        }
+    }
+    //
+    // Sessions last updated between min_age and max_age ago
+    //
+    pub fn list_sessions_by_age(
+        &mut self,
+        min_age: Option<Duration>,
+        max_age: Option<Duration>,
+    ) -> Result<Vec<String>, easy_error::Error> {
+        let mut res: Vec<String> = Vec::new();
+        for (name, updated) in self.session_updates()?.into_iter() {
+            let age = DeepThoughtRouter::session_age(updated);
+            match min_age {
+                Some(min_age) if age < min_age => continue,
+                _ => {}
+            }
+            match max_age {
+                Some(max_age) if age > max_age => continue,
+                _ => {}
+            }
+            res.push(name);
+        }
+        res.sort();
+        Ok(res)
+    }
+    //
+    // Drops sessions older than the TTL, returns how many were removed
+    //
+    pub fn expire_sessions(&mut self) -> Result<usize, easy_error::Error> {
+        let expired: Vec<String> = self
+            .session_updates()?
+            .into_iter()
+            .filter(|(_, updated)| self.is_expired(*updated))
+            .map(|(name, _)| name)
+            .collect();
+        for name in expired.iter() {
+            self.drop_session(name)?;
+        }
+        Ok(expired.len())
+    }
+    pub fn set_session_compaction(
+        &mut self,
+        name: &str,
+        compaction: Option<DeepThoughtCompaction>,
+    ) -> Result<(), easy_error::Error> {
+        match self.sessions.get_mut(name) {
+            Some(context) => {
+                context.set_compaction(compaction);
+                Ok(())
+            }
+            None => bail!("Session not found"),
+        }
+    }
+    //
+    // Summarizes older turns of the session with the model of the given ctx route
+    //
+    pub fn compact_session(&mut self, name: &str, route: &str) -> Result<bool, easy_error::Error> {
+        let context = match self.sessions.get_mut(name) {
+            Some(context) => context,
+            None => bail!("Session not found"),
+        };
+        let model = match self.ctx_routes.get_mut(route) {
+            Some(model) => model,
+            None => bail!("Context route not found"),
+        };
+        context.compact(model)
+    }
+    //
+    // Compacts the session with the ctx route named as its summarizer, other
+    // summarizers are left to the model running the session
+    //
+    pub(crate) fn compact_session_with_summarizer(
+        &mut self,
+        name: &str,
+    ) -> Result<bool, easy_error::Error> {
+        let route = match self.sessions.get(name) {
+            Some(context) => match context.compaction {
+                Some(DeepThoughtCompaction {
+                    summarizer: Some(DeepThoughtSummarizer::Route(ref route)),
+                    ..
+                }) => route.clone(),
+                _ => return Ok(false),
+            },
+            None => bail!("Session not found"),
+        };
+        if !self.ctx_routes.contains_key(&route) {
+            log::debug!(
+                "Summarizer route {} not found, the session model summarizes",
+                route
+            );
+            return Ok(false);
+        }
+        self.compact_session(name, &route)
     }
 }
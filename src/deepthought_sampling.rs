@@ -0,0 +1,191 @@
+extern crate log;
+
+use crate::*;
+
+use llama_cpp_2::sampling::LlamaSampler;
+
+pub const DEFAULT_TEMPERATURE: f32 = 0.8;
+pub const DEFAULT_MIN_P: f32 = 0.05;
+pub const DEFAULT_REPEAT_LAST_N: i32 = 64;
+pub const DEFAULT_SEED: u32 = 1337;
+
+//
+// Number of candidates mirostat v1 uses to estimate s_hat
+//
+const MIROSTAT_M: i32 = 100;
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        SamplingParams {
+            temperature: DEFAULT_TEMPERATURE,
+            top_k: None,
+            top_p: 1.0,
+            min_p: DEFAULT_MIN_P,
+            typical: 1.0,
+            repeat_last_n: DEFAULT_REPEAT_LAST_N,
+            repeat_penalty: 1.0,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            mirostat: Mirostat::Disabled,
+            seed: DEFAULT_SEED,
+            max_new_tokens: None,
+            stop: Vec::new(),
+            grammar: None,
+        }
+    }
+}
+
+impl SamplingParams {
+    pub fn new() -> Self {
+        SamplingParams::default()
+    }
+
+    //
+    // Greedy decoding, the same prompt always produces the same output
+    //
+    pub fn deterministic() -> Self {
+        SamplingParams::default().temperature(0.0)
+    }
+
+    pub fn creative() -> Self {
+        SamplingParams::default()
+            .temperature(1.1)
+            .top_p(0.95)
+            .repeat_penalty(1.1)
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn top_k(mut self, top_k: i32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    pub fn min_p(mut self, min_p: f32) -> Self {
+        self.min_p = min_p;
+        self
+    }
+
+    pub fn typical(mut self, typical: f32) -> Self {
+        self.typical = typical;
+        self
+    }
+
+    pub fn repeat_last_n(mut self, repeat_last_n: i32) -> Self {
+        self.repeat_last_n = repeat_last_n;
+        self
+    }
+
+    pub fn repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = repeat_penalty;
+        self
+    }
+
+    pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = presence_penalty;
+        self
+    }
+
+    pub fn mirostat(mut self, mirostat: Mirostat) -> Self {
+        self.mirostat = mirostat;
+        self
+    }
+
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn max_new_tokens(mut self, max_new_tokens: usize) -> Self {
+        self.max_new_tokens = Some(max_new_tokens);
+        self
+    }
+
+    pub fn stop(mut self, stop: &str) -> Self {
+        self.stop.push(stop.to_string());
+        self
+    }
+
+    pub fn grammar(mut self, grammar: &str) -> Self {
+        self.grammar = Some(grammar.to_string());
+        self
+    }
+
+    pub fn has_penalties(&self) -> bool {
+        self.repeat_penalty != 1.0 || self.frequency_penalty != 0.0 || self.presence_penalty != 0.0
+    }
+
+    pub fn sampler(&self, model: &LlamaModel) -> Result<LlamaSampler, Error> {
+        let mut chain: Vec<LlamaSampler> = Vec::new();
+        match self.grammar {
+            Some(ref grammar) => match LlamaSampler::grammar(model, grammar, "root") {
+                Ok(grammar_sampler) => chain.push(grammar_sampler),
+                Err(err) => {
+                    return Err(Error::InternalNativeError(format!(
+                        "Invalid grammar: {:?}",
+                        err
+                    )));
+                }
+            },
+            None => {}
+        }
+        if self.has_penalties() {
+            chain.push(LlamaSampler::penalties(
+                self.repeat_last_n,
+                self.repeat_penalty,
+                self.frequency_penalty,
+                self.presence_penalty,
+            ));
+        }
+        if self.temperature <= 0.0 {
+            chain.push(LlamaSampler::greedy());
+            return Ok(LlamaSampler::chain_simple(chain));
+        }
+        match self.mirostat {
+            Mirostat::V1 { tau, eta } => {
+                chain.push(LlamaSampler::temp(self.temperature));
+                chain.push(LlamaSampler::mirostat(
+                    model.n_vocab(),
+                    self.seed,
+                    tau,
+                    eta,
+                    MIROSTAT_M,
+                ));
+            }
+            Mirostat::V2 { tau, eta } => {
+                chain.push(LlamaSampler::temp(self.temperature));
+                chain.push(LlamaSampler::mirostat_v2(self.seed, tau, eta));
+            }
+            Mirostat::Disabled => {
+                if let Some(top_k) = self.top_k {
+                    chain.push(LlamaSampler::top_k(top_k));
+                }
+                if self.typical < 1.0 {
+                    chain.push(LlamaSampler::typical(self.typical, 1));
+                }
+                if self.top_p < 1.0 {
+                    chain.push(LlamaSampler::top_p(self.top_p, 1));
+                }
+                if self.min_p > 0.0 {
+                    chain.push(LlamaSampler::min_p(self.min_p, 1));
+                }
+                chain.push(LlamaSampler::temp(self.temperature));
+                chain.push(LlamaSampler::dist(self.seed));
+            }
+        }
+        Ok(LlamaSampler::chain_simple(chain))
+    }
+}
@@ -0,0 +1,180 @@
+extern crate log;
+
+use easy_error::bail;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::*;
+
+use llama_cpp_2::{llama_batch::LlamaBatch, model::AddBos};
+
+const SESSION_MAGIC: &[u8; 8] = b"DTSESSN1";
+
+//
+// Reads a length prefixed block, the length is checked against what is left
+// of the file before anything is allocated
+//
+fn read_block(file: &mut impl Read, remaining: &mut u64) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 8];
+    file.read_exact(&mut len)?;
+    *remaining = remaining.saturating_sub(len.len() as u64);
+    let len = u64::from_le_bytes(len);
+    if len > *remaining {
+        return Err(Error::IoError(format!(
+            "Session block of {} bytes exceeds the {} bytes left in the file",
+            len, *remaining
+        )));
+    }
+    *remaining -= len;
+    let mut block = vec![0u8; len as usize];
+    file.read_exact(&mut block)?;
+    Ok(block)
+}
+
+impl DeepThoughtSession {
+    //
+    // KV state is only valid for the weights that produced it
+    //
+    pub fn matches_model(&self, model: &DeepThoughtModel) -> bool {
+        self.model_path == model.model_path
+            && self.n_vocab == model.model.n_vocab()
+            && self.n_ctx_train == model.model.n_ctx_train()
+            && self.n_embd == model.model.n_embd()
+    }
+}
+
+impl DeepThoughtModel {
+    //
+    // Decodes the current message history so the KV cache covers it
+    //
+    pub fn prime_kv_cache(&mut self) -> Result<(), Error> {
+        let chat_template = self.active_chat_template()?;
+        let messages = DeepThoughtMessage::to_llama_messages(&self.messages)?;
+        let prompt = self
+            .model
+            .apply_chat_template(&chat_template, &messages, false)?;
+        let tokens = self.model.str_to_token(&prompt, AddBos::Always)?;
+
+        let mut cache = self.take_kv_cache()?;
+        let n_ctx = cache.n_ctx();
+        if tokens.len() >= n_ctx {
+            self.kv_cache = Some(cache);
+            return Err(Error::ContextSize {
+                maximum: n_ctx,
+                actual: tokens.len(),
+            });
+        }
+
+        let mut batch = LlamaBatch::new(self.batch_size, 1);
+        let n_past = cache.trim(&tokens);
+        cache.with_context(|context| {
+            DeepThoughtKvCache::decode_tokens(context, &mut batch, &tokens, n_past, self.batch_size)
+        })?;
+        cache.tokens = tokens;
+        self.kv_cache = Some(cache);
+        Ok(())
+    }
+
+    pub fn save_session(&mut self, path: &str) -> Result<(), easy_error::Error> {
+        match self.prime_kv_cache() {
+            Ok(_) => {}
+            Err(err) => bail!("Error evaluating session history: {:?}", err),
+        }
+        match self.write_session(path) {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("Error saving session to {}: {:?}", path, err),
+        }
+    }
+
+    pub fn load_session(&mut self, path: &str) -> Result<(), easy_error::Error> {
+        let (session, state) = match DeepThoughtModel::read_session(path) {
+            Ok(session) => session,
+            Err(err) => bail!("Error loading session from {}: {:?}", path, err),
+        };
+        self.system_prompt = session.system_prompt;
+        self.messages = session.messages;
+        if !session.matches_model(self) || state.is_empty() {
+            log::debug!("Session {} has no usable KV state for this model", path);
+            self.kv_cache = None;
+            return Ok(());
+        }
+        let mut cache = match self.take_kv_cache() {
+            Ok(cache) => cache,
+            Err(err) => bail!("Error creating context for session {}: {:?}", path, err),
+        };
+        let tokens = session.tokens.into_iter().map(LlamaToken::new).collect();
+        if cache.load_state(&state, tokens) {
+            self.kv_cache = Some(cache);
+        } else {
+            log::debug!("Session {} KV state does not fit this context", path);
+            self.kv_cache = None;
+        }
+        Ok(())
+    }
+
+    fn write_session(&mut self, path: &str) -> Result<(), Error> {
+        let (tokens, state): (Vec<i32>, Vec<u8>) = match self.kv_cache {
+            Some(ref mut cache) => (
+                cache.tokens.iter().map(|token| token.0).collect(),
+                cache.state(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        let session = DeepThoughtSession {
+            system_prompt: self.system_prompt.clone(),
+            messages: self.messages.clone(),
+            model_path: self.model_path.clone(),
+            n_vocab: self.model.n_vocab(),
+            n_ctx_train: self.model.n_ctx_train(),
+            n_embd: self.model.n_embd(),
+            tokens,
+        };
+        let header = match serde_json::to_vec(&session) {
+            Ok(header) => header,
+            Err(err) => return Err(Error::IoError(err.to_string())),
+        };
+        // Written to a temporary file first so a failed write never truncates
+        // the previous session
+        let tmp_path = format!("{}.tmp", path);
+        match DeepThoughtModel::write_session_file(&tmp_path, &header, &state) {
+            Ok(_) => {}
+            Err(err) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn write_session_file(path: &str, header: &[u8], state: &[u8]) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(SESSION_MAGIC)?;
+        file.write_all(&(header.len() as u64).to_le_bytes())?;
+        file.write_all(header)?;
+        file.write_all(&(state.len() as u64).to_le_bytes())?;
+        file.write_all(state)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    fn read_session(path: &str) -> Result<(DeepThoughtSession, Vec<u8>), Error> {
+        let file = File::open(path)?;
+        let mut remaining = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != SESSION_MAGIC {
+            return Err(Error::IoError("Not a DeepThought session file".to_string()));
+        }
+        remaining = remaining.saturating_sub(magic.len() as u64);
+        let header = read_block(&mut file, &mut remaining)?;
+        let session: DeepThoughtSession = match serde_json::from_slice(&header) {
+            Ok(session) => session,
+            Err(err) => return Err(Error::IoError(err.to_string())),
+        };
+        let state = read_block(&mut file, &mut remaining)?;
+        Ok((session, state))
+    }
+}
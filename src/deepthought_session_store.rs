@@ -0,0 +1,186 @@
+extern crate log;
+
+use easy_error::bail;
+use std::path::PathBuf;
+
+use crate::*;
+
+const SESSION_FILE_EXTENSION: &str = "json";
+
+//
+// Session names are kept readable in file names, every byte outside of
+// [A-Za-z0-9_-] is written as %XX
+//
+fn encode_session_name(name: &str) -> String {
+    let mut res = String::new();
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'_' || b == b'-' {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+    res
+}
+
+fn decode_session_name(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut res: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut n = 0;
+    while n < bytes.len() {
+        if bytes[n] == b'%' {
+            let hex = encoded.get(n + 1..n + 3)?;
+            res.push(u8::from_str_radix(hex, 16).ok()?);
+            n += 3;
+        } else {
+            res.push(bytes[n]);
+            n += 1;
+        }
+    }
+    String::from_utf8(res).ok()
+}
+
+fn decode_record(name: &str, data: &[u8]) -> Result<DeepThoughtContextRecord, easy_error::Error> {
+    match serde_json::from_slice::<DeepThoughtContextRecord>(data) {
+        Ok(record) => Ok(record),
+        Err(err) => bail!("Error deserializing session {}: {}", name, err),
+    }
+}
+
+impl DeepThoughtFileSessionStore {
+    pub fn new(path: &str) -> Result<Self, easy_error::Error> {
+        match std::fs::create_dir_all(path) {
+            Ok(_) => {}
+            Err(err) => bail!("Error creating session directory {}: {}", path, err),
+        }
+        Ok(DeepThoughtFileSessionStore {
+            path: path.to_string(),
+        })
+    }
+
+    fn session_path(&self, name: &str) -> PathBuf {
+        PathBuf::from(&self.path).join(format!(
+            "{}.{}",
+            encode_session_name(name),
+            SESSION_FILE_EXTENSION
+        ))
+    }
+}
+
+impl SessionStore for DeepThoughtFileSessionStore {
+    fn save(&self, name: &str, record: &DeepThoughtContextRecord) -> Result<(), easy_error::Error> {
+        let data = match serde_json::to_vec_pretty(record) {
+            Ok(data) => data,
+            Err(err) => bail!("Error serializing session {}: {}", name, err),
+        };
+        // Write to a temporary file first so a crash never leaves a truncated session
+        let path = self.session_path(name);
+        let tmp_path = path.with_extension("tmp");
+        match std::fs::write(&tmp_path, data) {
+            Ok(_) => {}
+            Err(err) => bail!("Error writing session {}: {}", name, err),
+        }
+        match std::fs::rename(&tmp_path, &path) {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("Error writing session {}: {}", name, err),
+        }
+    }
+
+    fn load(&self, name: &str) -> Result<Option<DeepThoughtContextRecord>, easy_error::Error> {
+        let path = self.session_path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(decode_record(name, &data)?)),
+            Err(err) => bail!("Error reading session {}: {}", name, err),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, easy_error::Error> {
+        let path = self.session_path(name);
+        if !path.exists() {
+            return Ok(false);
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => Ok(true),
+            Err(err) => bail!("Error deleting session {}: {}", name, err),
+        }
+    }
+
+    fn names(&self) -> Result<Vec<String>, easy_error::Error> {
+        let entries = match std::fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(err) => bail!("Error reading session directory {}: {}", self.path, err),
+        };
+        let mut res: Vec<String> = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SESSION_FILE_EXTENSION) {
+                continue;
+            }
+            match path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(decode_session_name)
+            {
+                Some(name) => res.push(name),
+                None => log::debug!("Skipping unexpected file in session directory: {:?}", path),
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+}
+
+impl DeepThoughtSledSessionStore {
+    pub fn new(path: &str) -> Result<Self, easy_error::Error> {
+        match sled::open(path) {
+            Ok(db) => Ok(DeepThoughtSledSessionStore { db }),
+            Err(err) => bail!("Error opening session database {}: {}", path, err),
+        }
+    }
+}
+
+impl SessionStore for DeepThoughtSledSessionStore {
+    fn save(&self, name: &str, record: &DeepThoughtContextRecord) -> Result<(), easy_error::Error> {
+        let data = match serde_json::to_vec(record) {
+            Ok(data) => data,
+            Err(err) => bail!("Error serializing session {}: {}", name, err),
+        };
+        match self.db.insert(name.as_bytes(), data) {
+            Ok(_) => {}
+            Err(err) => bail!("Error writing session {}: {}", name, err),
+        }
+        match self.db.flush() {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("Error flushing session {}: {}", name, err),
+        }
+    }
+
+    fn load(&self, name: &str) -> Result<Option<DeepThoughtContextRecord>, easy_error::Error> {
+        match self.db.get(name.as_bytes()) {
+            Ok(Some(data)) => Ok(Some(decode_record(name, &data)?)),
+            Ok(None) => Ok(None),
+            Err(err) => bail!("Error reading session {}: {}", name, err),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, easy_error::Error> {
+        match self.db.remove(name.as_bytes()) {
+            Ok(removed) => Ok(removed.is_some()),
+            Err(err) => bail!("Error deleting session {}: {}", name, err),
+        }
+    }
+
+    fn names(&self) -> Result<Vec<String>, easy_error::Error> {
+        let mut res: Vec<String> = Vec::new();
+        for key in self.db.iter().keys() {
+            match key {
+                Ok(key) => res.push(String::from_utf8_lossy(&key).to_string()),
+                Err(err) => bail!("Error listing sessions: {}", err),
+            }
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,626 @@
+extern crate log;
+
+use easy_error::bail;
+
+use crate::*;
+
+use vecstore::TextSplitter;
+use vecstore::text_splitter::RecursiveCharacterTextSplitter;
+
+//
+// Tokens reserved for BOS and EOS/SEP added when a chunk is embedded
+//
+const EMBED_SPECIAL_TOKENS: usize = 2;
+
+//
+// Separator between Markdown headings in heading_path metadata
+//
+pub const HEADING_PATH_SEPARATOR: &str = " > ";
+
+//
+// GitHub style anchor of a heading, lowercase words joined by '-'
+//
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                Some(c)
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+//
+// Splits "Title {#anchor}" into the title and its anchor, headings without
+// an explicit anchor get the slug of their title
+//
+pub fn heading_anchor(heading: &str) -> (String, String) {
+    let heading = heading.trim();
+    if heading.ends_with('}') {
+        match heading.rfind("{#") {
+            Some(start) => {
+                let id = heading[start + 2..heading.len() - 1].trim();
+                if !id.is_empty() && !id.contains(char::is_whitespace) {
+                    return (heading[..start].trim().to_string(), id.to_string());
+                }
+            }
+            None => {}
+        }
+    }
+    (heading.to_string(), slugify(heading))
+}
+
+//
+// Start offsets of windows of size tokens advancing by size - overlap
+//
+pub fn token_windows(n_tokens: usize, size: usize, overlap: usize) -> Vec<(usize, usize)> {
+    match fit_token_windows(n_tokens, size, overlap, |_, end| Ok::<usize, ()>(end)) {
+        Ok(res) => res,
+        Err(_) => Vec::new(),
+    }
+}
+
+//
+// Windows of at most size tokens where fit tells where a window really ends,
+// the next window starts overlap tokens before that end so no token is lost
+//
+pub fn fit_token_windows<E>(
+    n_tokens: usize,
+    size: usize,
+    overlap: usize,
+    mut fit: impl FnMut(usize, usize) -> Result<usize, E>,
+) -> Result<Vec<(usize, usize)>, E> {
+    let mut res: Vec<(usize, usize)> = Vec::new();
+    if n_tokens == 0 || size == 0 {
+        return Ok(res);
+    }
+    let mut start = 0;
+    loop {
+        let end = std::cmp::min(start + size, n_tokens);
+        let end = std::cmp::min(std::cmp::max(fit(start, end)?, start + 1), end);
+        res.push((start, end));
+        if end == n_tokens {
+            break;
+        }
+        start = std::cmp::max(start + 1, end.saturating_sub(overlap));
+    }
+    Ok(res)
+}
+
+pub(crate) fn split_characters(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+    let splitter = RecursiveCharacterTextSplitter::new(chunk_size, chunk_overlap);
+    match splitter.split_text(text) {
+        Ok(chunks) => chunks,
+        Err(err) => {
+            log::debug!("Failed to split text: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+//
+// Greedily joins units up to chunk_size characters, the next chunk repeats
+// trailing units of the previous one that fit into chunk_overlap. Returns the
+// joined text with the first and last unit index. A unit longer than
+// chunk_size becomes a chunk of its own.
+//
+pub fn pack_units(
+    units: &[String],
+    separator: &str,
+    chunk_size: usize,
+    chunk_overlap: usize,
+) -> Vec<(String, usize, usize)> {
+    let mut res: Vec<(String, usize, usize)> = Vec::new();
+    let mut start = 0;
+    while start < units.len() {
+        let mut end = start;
+        let mut len = units[start].len();
+        while end + 1 < units.len() && len + separator.len() + units[end + 1].len() <= chunk_size {
+            end += 1;
+            len += separator.len() + units[end].len();
+        }
+        res.push((units[start..=end].join(separator), start, end));
+        if end + 1 >= units.len() {
+            break;
+        }
+        let mut next = end + 1;
+        let mut overlap = 0;
+        while next > start + 1 {
+            let unit_len = units[next - 1].len() + separator.len();
+            if overlap + unit_len > chunk_overlap {
+                break;
+            }
+            overlap += unit_len;
+            next -= 1;
+        }
+        start = next;
+    }
+    res
+}
+
+//
+// Sentence ends at ., ! or ? followed by whitespace, and at blank lines
+//
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        current.push(c);
+        let at_end = match c {
+            '.' | '!' | '?' => match chars.peek() {
+                Some(next) => next.is_whitespace(),
+                None => true,
+            },
+            '\n' => matches!(chars.peek(), Some('\n')),
+            _ => false,
+        };
+        if at_end && !current.trim().is_empty() {
+            res.push(current.trim().to_string());
+            current.clear();
+        }
+    }
+    if !current.trim().is_empty() {
+        res.push(current.trim().to_string());
+    }
+    res
+}
+
+fn chunk(text: &str) -> DeepThoughtChunk {
+    DeepThoughtChunk {
+        text: text.to_string(),
+        metadata: HashMap::new(),
+    }
+}
+
+//
+// Sections under each heading, fenced code blocks are never taken for headings
+//
+fn split_markdown(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<DeepThoughtChunk> {
+    let mut sections: Vec<(Vec<String>, Option<String>, String)> = Vec::new();
+    let mut headings: Vec<(usize, String, String)> = Vec::new();
+    let mut body: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    let mut flush = |headings: &Vec<(usize, String, String)>, body: &mut Vec<&str>| {
+        let text = body.join("\n");
+        let has_content = text
+            .lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+        if has_content {
+            let path = headings.iter().map(|(_, title, _)| title.clone()).collect();
+            let anchor = headings.last().map(|(_, _, anchor)| anchor.clone());
+            sections.push((path, anchor, text.trim().to_string()));
+        }
+        body.clear();
+    };
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        let is_heading = !in_fence
+            && (1..=6).contains(&level)
+            && trimmed[level..].starts_with(' ')
+            && line.len() - trimmed.len() < 4;
+        if is_heading {
+            flush(&headings, &mut body);
+            headings.retain(|(heading_level, _, _)| *heading_level < level);
+            let (title, anchor) = heading_anchor(trimmed[level..].trim().trim_end_matches('#'));
+            headings.push((level, title, anchor));
+        }
+        body.push(line);
+    }
+    flush(&headings, &mut body);
+
+    let mut res: Vec<DeepThoughtChunk> = Vec::new();
+    for (path, anchor, text) in sections.iter() {
+        let parts = if text.len() <= chunk_size {
+            vec![text.clone()]
+        } else {
+            split_characters(text, chunk_size, chunk_overlap)
+        };
+        for part in parts.iter() {
+            let mut c = chunk(part);
+            c.metadata.insert(
+                "heading_path".to_string(),
+                serde_json::json!(path.join(HEADING_PATH_SEPARATOR)),
+            );
+            c.metadata
+                .insert("headings".to_string(), serde_json::json!(path));
+            match anchor {
+                Some(anchor) => {
+                    c.metadata
+                        .insert("anchor".to_string(), serde_json::json!(anchor));
+                }
+                None => {}
+            }
+            res.push(c);
+        }
+    }
+    res
+}
+
+impl DeepThoughtLanguage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeepThoughtLanguage::Rust => "rust",
+            DeepThoughtLanguage::Python => "python",
+            DeepThoughtLanguage::JavaScript => "javascript",
+            DeepThoughtLanguage::Go => "go",
+            DeepThoughtLanguage::Java => "java",
+            DeepThoughtLanguage::C => "c",
+        }
+    }
+
+    //
+    // Guesses the language from a file extension
+    //
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "rs" => Some(DeepThoughtLanguage::Rust),
+            "py" => Some(DeepThoughtLanguage::Python),
+            "js" | "jsx" | "mjs" | "ts" | "tsx" => Some(DeepThoughtLanguage::JavaScript),
+            "go" => Some(DeepThoughtLanguage::Go),
+            "java" | "kt" | "cs" => Some(DeepThoughtLanguage::Java),
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" => Some(DeepThoughtLanguage::C),
+            _ => None,
+        }
+    }
+
+    fn strip_modifiers<'a>(line: &'a str, modifiers: &[&str]) -> &'a str {
+        let mut line = line;
+        loop {
+            match modifiers
+                .iter()
+                .find(|modifier| line.starts_with(*modifier))
+            {
+                Some(modifier) => line = line[modifier.len()..].trim_start(),
+                None => return line,
+            }
+        }
+    }
+
+    //
+    // Whether the line starts a function, class or type definition
+    //
+    pub fn is_definition(&self, line: &str) -> bool {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim_start();
+        match self {
+            DeepThoughtLanguage::Rust => {
+                let line = DeepThoughtLanguage::strip_modifiers(
+                    line,
+                    &[
+                        "pub(crate) ",
+                        "pub(super) ",
+                        "pub ",
+                        "async ",
+                        "unsafe ",
+                        "const ",
+                        "extern \"C\" ",
+                    ],
+                );
+                indent <= 4
+                    && [
+                        "fn ",
+                        "struct ",
+                        "enum ",
+                        "impl ",
+                        "impl<",
+                        "trait ",
+                        "mod ",
+                        "macro_rules!",
+                    ]
+                    .iter()
+                    .any(|keyword| line.starts_with(keyword))
+            }
+            DeepThoughtLanguage::Python => {
+                indent <= 4
+                    && ["def ", "async def ", "class "]
+                        .iter()
+                        .any(|keyword| line.starts_with(keyword))
+            }
+            DeepThoughtLanguage::JavaScript => {
+                let line = DeepThoughtLanguage::strip_modifiers(
+                    line,
+                    &["export ", "default ", "async ", "abstract "],
+                );
+                indent == 0
+                    && ["function", "class ", "interface ", "type ", "enum "]
+                        .iter()
+                        .any(|keyword| line.starts_with(keyword))
+            }
+            DeepThoughtLanguage::Go => {
+                indent == 0 && (line.starts_with("func ") || line.starts_with("type "))
+            }
+            DeepThoughtLanguage::Java => {
+                let line = DeepThoughtLanguage::strip_modifiers(
+                    line,
+                    &[
+                        "public ",
+                        "private ",
+                        "protected ",
+                        "internal ",
+                        "static ",
+                        "final ",
+                        "abstract ",
+                        "synchronized ",
+                        "override ",
+                        "sealed ",
+                    ],
+                );
+                let statement = [
+                    "if ", "if(", "for ", "for(", "while ", "while(", "switch ", "return ", "new ",
+                    "catch ", "else",
+                ]
+                .iter()
+                .any(|keyword| line.starts_with(keyword));
+                indent <= 4
+                    && !statement
+                    && (["class ", "interface ", "enum ", "record "]
+                        .iter()
+                        .any(|keyword| line.starts_with(keyword))
+                        || (line.contains('(') && !line.ends_with(';') && !line.starts_with('@')))
+            }
+            DeepThoughtLanguage::C => {
+                indent == 0
+                    && !line.is_empty()
+                    && !line.starts_with('#')
+                    && !line.starts_with('}')
+                    && !line.starts_with("//")
+                    && !line.starts_with("/*")
+                    && !line.starts_with('*')
+                    && (line.contains('(') && !line.ends_with(';')
+                        || [
+                            "struct ",
+                            "class ",
+                            "enum ",
+                            "union ",
+                            "typedef ",
+                            "namespace ",
+                        ]
+                        .iter()
+                        .any(|keyword| line.starts_with(keyword))
+                            && !line.ends_with(';'))
+            }
+        }
+    }
+
+    //
+    // Comments, attributes and decorators right above a definition belong to it
+    //
+    fn is_preamble(&self, line: &str) -> bool {
+        let line = line.trim_start();
+        match self {
+            DeepThoughtLanguage::Rust => line.starts_with("//") || line.starts_with("#["),
+            DeepThoughtLanguage::Python => line.starts_with('@') || line.starts_with('#'),
+            DeepThoughtLanguage::Java => {
+                line.starts_with('@')
+                    || line.starts_with("//")
+                    || line.starts_with("/*")
+                    || line.starts_with('*')
+            }
+            _ => line.starts_with("//") || line.starts_with("/*") || line.starts_with('*'),
+        }
+    }
+}
+
+//
+// Blocks starting at definitions, small neighbouring blocks are packed together
+//
+fn split_code(
+    text: &str,
+    language: DeepThoughtLanguage,
+    chunk_size: usize,
+    chunk_overlap: usize,
+) -> Vec<DeepThoughtChunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut starts: Vec<usize> = vec![0];
+    for (n, line) in lines.iter().enumerate() {
+        if n == 0 || !language.is_definition(line) {
+            continue;
+        }
+        let mut start = n;
+        while start > 0 && language.is_preamble(lines[start - 1]) {
+            start -= 1;
+        }
+        if start > *starts.last().unwrap_or(&0) {
+            starts.push(start);
+        }
+    }
+    let mut blocks: Vec<String> = Vec::new();
+    for (n, start) in starts.iter().enumerate() {
+        let end = match starts.get(n + 1) {
+            Some(end) => *end,
+            None => lines.len(),
+        };
+        blocks.push(lines[*start..end].join("\n"));
+    }
+
+    let mut res: Vec<DeepThoughtChunk> = Vec::new();
+    for (text, first, last) in pack_units(&blocks, "\n", chunk_size, 0) {
+        let line_start = starts[first];
+        let line_end = match starts.get(last + 1) {
+            Some(line_end) => *line_end,
+            None => lines.len(),
+        };
+        let symbol = lines[line_start..line_end]
+            .iter()
+            .find(|line| language.is_definition(line))
+            .map(|line| line.trim().to_string());
+        let parts: Vec<(String, usize)> = if text.len() <= chunk_size {
+            vec![(text, line_start)]
+        } else {
+            // A single definition longer than chunk_size is cut between lines
+            let block_lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+            pack_units(&block_lines, "\n", chunk_size, chunk_overlap)
+                .into_iter()
+                .map(|(text, first_line, _)| (text, line_start + first_line))
+                .collect()
+        };
+        for (part, part_start) in parts.iter() {
+            if part.trim().is_empty() {
+                continue;
+            }
+            let mut c = chunk(part);
+            c.metadata
+                .insert("language".to_string(), serde_json::json!(language.name()));
+            c.metadata
+                .insert("line_start".to_string(), serde_json::json!(part_start + 1));
+            c.metadata.insert(
+                "line_end".to_string(),
+                serde_json::json!(part_start + part.lines().count()),
+            );
+            match symbol {
+                Some(ref symbol) => {
+                    c.metadata
+                        .insert("symbol".to_string(), serde_json::json!(symbol));
+                }
+                None => {}
+            }
+            res.push(c);
+        }
+    }
+    res
+}
+
+fn split_lines(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<DeepThoughtChunk> {
+    let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+    let mut res: Vec<DeepThoughtChunk> = Vec::new();
+    for (text, first, last) in pack_units(&lines, "\n", chunk_size, chunk_overlap) {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let mut c = chunk(&text);
+        c.metadata
+            .insert("line_start".to_string(), serde_json::json!(first + 1));
+        c.metadata
+            .insert("line_end".to_string(), serde_json::json!(last + 1));
+        res.push(c);
+    }
+    res
+}
+
+impl DeepThoughtSplitter {
+    //
+    // Splits without a tokenizer, Tokens falls back to Characters
+    //
+    pub fn split(
+        &self,
+        text: &str,
+        chunk_size: usize,
+        chunk_overlap: usize,
+    ) -> Vec<DeepThoughtChunk> {
+        match self {
+            DeepThoughtSplitter::Characters | DeepThoughtSplitter::Tokens => {
+                split_characters(text, chunk_size, chunk_overlap)
+                    .iter()
+                    .map(|text| chunk(text))
+                    .collect()
+            }
+            DeepThoughtSplitter::Markdown => split_markdown(text, chunk_size, chunk_overlap),
+            DeepThoughtSplitter::Code(language) => {
+                split_code(text, *language, chunk_size, chunk_overlap)
+            }
+            DeepThoughtSplitter::Sentences => {
+                pack_units(&split_sentences(text), " ", chunk_size, chunk_overlap)
+                    .into_iter()
+                    .map(|(text, _, _)| chunk(&text))
+                    .collect()
+            }
+            DeepThoughtSplitter::Lines => split_lines(text, chunk_size, chunk_overlap),
+        }
+    }
+}
+
+impl DeepThoughtVecStore {
+    pub fn set_splitter(&mut self, splitter: DeepThoughtSplitter) {
+        self.splitter = splitter;
+    }
+
+    pub fn get_splitter(&self) -> DeepThoughtSplitter {
+        self.splitter
+    }
+
+    //
+    // Largest chunk in tokens the embedder takes together with the embedding prefix
+    //
+    pub fn max_chunk_tokens(
+        &self,
+        embedder: &DeepThoughtModel,
+    ) -> Result<usize, easy_error::Error> {
+        let prefix_tokens = match embedder.count_tokens(&format!("{} ", self.embedding_prefix)) {
+            Ok(prefix_tokens) => prefix_tokens,
+            Err(err) => bail!("Failed to tokenize embedding prefix: {:?}", err),
+        };
+        let limit = std::cmp::min(self.chunk_size, embedder.context_length);
+        match limit.checked_sub(prefix_tokens + EMBED_SPECIAL_TOKENS) {
+            Some(limit) if limit > 0 => Ok(limit),
+            _ => bail!(
+                "Embedding prefix leaves no room for chunks of {} tokens",
+                limit
+            ),
+        }
+    }
+
+    //
+    // Splits by the embedder tokenizer so that every chunk fits into the embedder
+    //
+    pub fn split_tokens(
+        &self,
+        text: &str,
+        embedder: &DeepThoughtModel,
+    ) -> Result<Vec<String>, easy_error::Error> {
+        let limit = self.max_chunk_tokens(embedder)?;
+        let overlap = std::cmp::min(self.chunk_overlap, limit / 2);
+        let tokens = match embedder.tokenize(text) {
+            Ok(tokens) => tokens,
+            Err(err) => bail!("Failed to tokenize text: {:?}", err),
+        };
+        let mut chunks: Vec<String> = Vec::new();
+        fit_token_windows(tokens.len(), limit, overlap, |start, end| {
+            // Detokenized text can tokenize a little differently, shrink until it fits
+            let mut end = end;
+            loop {
+                let chunk = match embedder.detokenize(&tokens[start..end]) {
+                    Ok(chunk) => chunk,
+                    Err(err) => bail!("Failed to detokenize chunk: {:?}", err),
+                };
+                let count = match embedder.count_tokens(&chunk) {
+                    Ok(count) => count,
+                    Err(err) => bail!("Failed to tokenize chunk: {:?}", err),
+                };
+                if count <= limit || end - start <= 1 {
+                    if !chunk.trim().is_empty() {
+                        chunks.push(chunk.trim().to_string());
+                    }
+                    return Ok(end);
+                }
+                end = std::cmp::max(end.saturating_sub(count - limit), start + 1);
+            }
+        })?;
+        Ok(chunks)
+    }
+
+    pub fn split_document(
+        &self,
+        text: &str,
+        splitter: DeepThoughtSplitter,
+        embedder: &DeepThoughtModel,
+    ) -> Result<Vec<DeepThoughtChunk>, easy_error::Error> {
+        match splitter {
+            DeepThoughtSplitter::Tokens => Ok(self
+                .split_tokens(text, embedder)?
+                .iter()
+                .map(|text| chunk(text))
+                .collect()),
+            splitter => Ok(splitter.split(text, self.chunk_size, self.chunk_overlap)),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+extern crate log;
+
+use crate::*;
+
+use std::io::Write;
+
+impl<F: FnMut(&str)> DeepThoughtStream<F> {
+    pub fn new(callback: F) -> Self {
+        DeepThoughtStream {
+            callback,
+            pending: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    //
+    // Emits whatever is left in the buffer (a truncated multibyte sequence
+    // becomes U+FFFD) and returns the complete text
+    //
+    pub fn finish(mut self) -> String {
+        if !self.pending.is_empty() {
+            let rest = String::from_utf8_lossy(&self.pending).to_string();
+            self.pending.clear();
+            self.emit(&rest);
+        }
+        self.text
+    }
+
+    fn emit(&mut self, piece: &str) {
+        if piece.is_empty() {
+            return;
+        }
+        self.text.push_str(piece);
+        (self.callback)(piece);
+    }
+
+    fn decode_pending(&mut self) {
+        loop {
+            let (valid_up_to, invalid_len) = match std::str::from_utf8(&self.pending) {
+                Ok(_) => (self.pending.len(), None),
+                Err(err) => match err.error_len() {
+                    Some(len) => (err.valid_up_to(), Some(len)),
+                    // Incomplete sequence at the end, wait for the next token
+                    None => (err.valid_up_to(), None),
+                },
+            };
+            let rest = self.pending.split_off(valid_up_to);
+            let piece = String::from_utf8_lossy(&self.pending).to_string();
+            self.pending = rest;
+            self.emit(&piece);
+            match invalid_len {
+                Some(len) => {
+                    self.pending.drain(..len);
+                    self.emit("\u{FFFD}");
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl<F: FnMut(&str)> Write for DeepThoughtStream<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.decode_pending();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+extern crate log;
+
+use easy_error::bail;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use took::Timer;
+
+use crate::deepthought_documents::{read_manifest, write_manifest};
+use crate::deepthought_ingest::source_path;
+use crate::*;
+
+//
+// Paths passed to ingest_path with their options, kept in the vector store directory
+//
+pub const SOURCE_MANIFEST: &str = "sources.json";
+
+//
+// A source under a tracked root is removed when the walk did not see it,
+// sources below a directory that could not be read are kept
+//
+pub fn source_removed(
+    source: &str,
+    roots: &[String],
+    seen: &HashSet<String>,
+    failed: &[String],
+) -> bool {
+    let path = Path::new(source);
+    roots.iter().any(|root| path.starts_with(root))
+        && !seen.contains(source)
+        && !failed.iter().any(|failed| path.starts_with(failed))
+}
+
+impl DeepThoughtSyncReport {
+    pub fn count(&self, status: &DeepThoughtIngestStatus) -> usize {
+        self.results
+            .iter()
+            .filter(|res| std::mem::discriminant(&res.status) == std::mem::discriminant(status))
+            .count()
+    }
+
+    pub fn paths(&self, status: &DeepThoughtIngestStatus) -> Vec<String> {
+        self.results
+            .iter()
+            .filter(|res| std::mem::discriminant(&res.status) == std::mem::discriminant(status))
+            .map(|res| res.path.clone())
+            .collect()
+    }
+
+    //
+    // Whether sync added, updated or removed anything, or would have with dry_run
+    //
+    pub fn has_changes(&self) -> bool {
+        self.results.iter().any(|res| {
+            matches!(
+                res.status,
+                DeepThoughtIngestStatus::Added
+                    | DeepThoughtIngestStatus::Updated
+                    | DeepThoughtIngestStatus::Removed
+            )
+        })
+    }
+}
+
+impl DeepThoughtVecStore {
+    fn source_manifest_path(path: &str) -> PathBuf {
+        PathBuf::from(path).join(SOURCE_MANIFEST)
+    }
+
+    pub(crate) fn load_sources(
+        path: &str,
+    ) -> Result<HashMap<String, DeepThoughtIngestOptions>, easy_error::Error> {
+        read_manifest(&DeepThoughtVecStore::source_manifest_path(path))
+    }
+
+    pub fn save_sources(&self) -> Result<(), easy_error::Error> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let data = match serde_json::to_vec_pretty(&self.sources) {
+            Ok(data) => data,
+            Err(err) => bail!("Error serializing source manifest: {}", err),
+        };
+        write_manifest(&DeepThoughtVecStore::source_manifest_path(path), &data)
+    }
+
+    pub fn list_source_paths(&self) -> Vec<String> {
+        let mut res: Vec<String> = self.sources.keys().cloned().collect();
+        res.sort();
+        res
+    }
+
+    //
+    // Stops tracking a path, its documents stay in the store
+    //
+    pub fn forget_source_path(&mut self, path: &str) -> bool {
+        let source = source_path(Path::new(path));
+        self.sources.remove(&source).is_some() || self.sources.remove(path).is_some()
+    }
+
+    //
+    // Source file -> content hash -> chunk ids of every ingested file
+    //
+    pub fn source_manifest(&self) -> Vec<DeepThoughtSource> {
+        let mut res: Vec<DeepThoughtSource> = Vec::new();
+        for document in self.documents.values() {
+            let source = match document.metadata.get("source") {
+                Some(serde_json::Value::String(source)) => source.clone(),
+                _ => continue,
+            };
+            let content_hash = match document.metadata.get("content_hash") {
+                Some(serde_json::Value::String(content_hash)) => content_hash.clone(),
+                _ => continue,
+            };
+            res.push(DeepThoughtSource {
+                source,
+                content_hash,
+                doc_id: document.id.clone(),
+                chunks: document.chunks.clone(),
+                updated: document.updated,
+            });
+        }
+        res.sort_by(|a, b| a.source.cmp(&b.source));
+        res
+    }
+
+    //
+    // Walks every path given to ingest_path again, re-embeds changed files,
+    // deletes documents of files that are gone and leaves the rest alone.
+    // With dry_run the report tells what would change and nothing is written.
+    //
+    pub fn sync_sources(
+        &mut self,
+        embedder: &DeepThoughtModel,
+        dry_run: bool,
+    ) -> Result<DeepThoughtSyncReport, easy_error::Error> {
+        let timer = Timer::new();
+        let mut results: Vec<DeepThoughtIngestResult> = Vec::new();
+        let mut gone_roots: Vec<String> = Vec::new();
+        // Nested paths see each other's files, so removals wait for every walk
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut failed: Vec<String> = Vec::new();
+        for root in self.list_source_paths().iter() {
+            let root_path = Path::new(root);
+            if !root_path.exists() {
+                gone_roots.push(root.clone());
+                continue;
+            }
+            let options = match self.sources.get(root) {
+                Some(options) => options.clone(),
+                None => continue,
+            };
+            let walk = self.walk_path(root_path, embedder, &options, dry_run)?;
+            results.extend(walk.results);
+            seen.extend(walk.seen);
+            failed.extend(walk.failed);
+        }
+        let roots = self.list_source_paths();
+        for source in self.source_manifest().iter() {
+            if !source_removed(&source.source, &roots, &seen, &failed) {
+                continue;
+            }
+            let removed_timer = Timer::new();
+            if !dry_run {
+                self.delete_document(&source.doc_id)?;
+            }
+            let mut res = DeepThoughtIngestResult::new(
+                Path::new(&source.source),
+                DeepThoughtIngestStatus::Removed,
+                &removed_timer,
+            );
+            res.doc_id = Some(source.doc_id.clone());
+            res.chunks = source.chunks.len();
+            results.push(res);
+        }
+        if !dry_run {
+            for root in gone_roots.iter() {
+                log::debug!("Source path {} is gone, no longer tracked", root);
+                self.sources.remove(root);
+            }
+        }
+        Ok(DeepThoughtSyncReport {
+            dry_run,
+            results,
+            duration: *timer.took().as_std(),
+        })
+    }
+}
@@ -0,0 +1,140 @@
+extern crate log;
+
+use crate::*;
+
+use llama_cpp_2::model::{AddBos, LlamaChatTemplate, Special};
+
+fn tokenize(model: &LlamaModel, text: &str) -> Result<Vec<LlamaToken>, Error> {
+    Ok(model.str_to_token(text, AddBos::Never)?)
+}
+
+//
+// Token bytes are joined before decoding, a multi-byte character may be
+// split between tokens
+//
+fn detokenize(model: &LlamaModel, tokens: &[LlamaToken]) -> Result<String, Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for token in tokens.iter() {
+        bytes.extend_from_slice(&model.token_to_bytes(*token, Special::Tokenize)?);
+    }
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+//
+// Text of the longest run of leading token pieces that decodes to valid UTF-8,
+// a character split between tokens is dropped instead of left as U+FFFD
+//
+pub fn valid_utf8_prefix(pieces: &[Vec<u8>]) -> String {
+    let mut bytes: Vec<u8> = pieces.concat();
+    let mut n = pieces.len();
+    while std::str::from_utf8(&bytes).is_err() && n > 0 {
+        n -= 1;
+        bytes.truncate(bytes.len() - pieces[n].len());
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn truncate_to_tokens(model: &LlamaModel, text: &str, n: usize) -> Result<String, Error> {
+    let tokens = tokenize(model, text)?;
+    if tokens.len() <= n {
+        return Ok(text.to_string());
+    }
+    let mut pieces: Vec<Vec<u8>> = Vec::new();
+    for token in tokens[..n].iter() {
+        pieces.push(model.token_to_bytes(*token, Special::Tokenize)?);
+    }
+    Ok(valid_utf8_prefix(&pieces))
+}
+
+fn count_chat_tokens(
+    model: &LlamaModel,
+    chat_template: &LlamaChatTemplate,
+    messages: &[DeepThoughtMessage],
+) -> Result<usize, Error> {
+    let messages = DeepThoughtMessage::to_llama_messages(messages)?;
+    let prompt = model.apply_chat_template(chat_template, &messages, true)?;
+    Ok(model.str_to_token(&prompt, AddBos::Always)?.len())
+}
+
+//
+// Fails with ContextSize when the messages leave no room for the answer
+//
+fn check_context(tokens: usize, context_length: usize) -> Result<usize, Error> {
+    if tokens >= context_length {
+        return Err(Error::ContextSize {
+            maximum: context_length,
+            actual: tokens,
+        });
+    }
+    Ok(tokens)
+}
+
+impl DeepThoughtModel {
+    pub fn tokenize(&self, text: &str) -> Result<Vec<LlamaToken>, Error> {
+        tokenize(&self.model, text)
+    }
+
+    pub fn detokenize(&self, tokens: &[LlamaToken]) -> Result<String, Error> {
+        detokenize(&self.model, tokens)
+    }
+
+    pub fn count_tokens(&self, text: &str) -> Result<usize, Error> {
+        Ok(tokenize(&self.model, text)?.len())
+    }
+
+    pub fn truncate_to_tokens(&self, text: &str, n: usize) -> Result<String, Error> {
+        truncate_to_tokens(&self.model, text, n)
+    }
+
+    //
+    // Number of tokens the messages take once the chat template is applied
+    //
+    pub fn count_chat_tokens(&self, messages: &[DeepThoughtMessage]) -> Result<usize, Error> {
+        let chat_template = self.active_chat_template()?;
+        count_chat_tokens(&self.model, &chat_template, messages)
+    }
+
+    //
+    // Tokens the history plus the prompt would take, checked against the context length
+    //
+    pub fn check_prompt(&self, prompt: &str) -> Result<usize, Error> {
+        let mut messages = self.messages.clone();
+        messages.push(DeepThoughtMessage::user(prompt));
+        check_context(self.count_chat_tokens(&messages)?, self.context_length)
+    }
+}
+
+impl DeepThoughtCtxModel {
+    pub fn tokenize(&self, text: &str) -> Result<Vec<LlamaToken>, Error> {
+        tokenize(&self.model, text)
+    }
+
+    pub fn detokenize(&self, tokens: &[LlamaToken]) -> Result<String, Error> {
+        detokenize(&self.model, tokens)
+    }
+
+    pub fn count_tokens(&self, text: &str) -> Result<usize, Error> {
+        Ok(tokenize(&self.model, text)?.len())
+    }
+
+    pub fn truncate_to_tokens(&self, text: &str, n: usize) -> Result<String, Error> {
+        truncate_to_tokens(&self.model, text, n)
+    }
+
+    //
+    // Number of tokens the messages take once the chat template is applied
+    //
+    pub fn count_chat_tokens(&self, messages: &[DeepThoughtMessage]) -> Result<usize, Error> {
+        let chat_template = self.active_chat_template()?;
+        count_chat_tokens(&self.model, &chat_template, messages)
+    }
+
+    //
+    // Tokens the context plus the prompt would take, checked against the context length
+    //
+    pub fn check_prompt(&self, prompt: &str, ctx: &DeepThoughtContext) -> Result<usize, Error> {
+        let mut messages = ctx.messages().clone();
+        messages.push(DeepThoughtMessage::user(prompt));
+        check_context(self.count_chat_tokens(&messages)?, self.context_length)
+    }
+}
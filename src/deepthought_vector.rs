@@ -7,8 +7,7 @@ use std::time::Duration;
 use took::Timer;
 
 use crate::*;
-use vecstore::text_splitter::RecursiveCharacterTextSplitter;
-use vecstore::{HybridQuery, TextSplitter};
+use vecstore::HybridQuery;
 use vecstore::{Metadata, Neighbor, VecStore};
 
 pub const DEFAULT_CHUNK_SIZE: usize = 1024;
@@ -17,6 +16,22 @@ pub const DEFAULT_K: usize = 10;
 pub const DEFAULT_ALPHA: f32 = 0.7;
 pub const DEFAULT_MAX_SCORE: f32 = 0.3;
 
+//
+// Filters are checked on query results, so filtered queries start by fetching
+// this many times k neighbors and double it until k of them match
+//
+pub const FILTER_OVERFETCH: usize = 4;
+
+impl From<Neighbor> for VecStoreNeighbors {
+    fn from(neighbor: Neighbor) -> Self {
+        VecStoreNeighbors {
+            id: neighbor.id,
+            score: neighbor.score,
+            metadata: neighbor.metadata.fields,
+        }
+    }
+}
+
 impl DeepThoughtVecStore {
     pub fn new(path: &str) -> Result<Self, easy_error::Error> {
         let conn = match VecStore::open(path) {
@@ -27,8 +42,11 @@ impl DeepThoughtVecStore {
         let vector = Self {
             path: Some(path.to_string()),
             conn: tvs,
+            documents: DeepThoughtVecStore::load_documents(path)?,
+            sources: DeepThoughtVecStore::load_sources(path)?,
             chunk_size: DEFAULT_CHUNK_SIZE,
             chunk_overlap: DEFAULT_CHUNK_OVERLAP,
+            splitter: DeepThoughtSplitter::default(),
             embedding_prefix: "".to_string(),
             k: DEFAULT_K,
             alpha: DEFAULT_ALPHA,
@@ -38,15 +56,7 @@ impl DeepThoughtVecStore {
         Ok(vector)
     }
     pub fn split_text(&self, text: &str) -> Vec<String> {
-        let splitter = RecursiveCharacterTextSplitter::new(self.chunk_size, self.chunk_overlap);
-        let chunks: Vec<String> = match splitter.split_text(text) {
-            Ok(chunks) => chunks,
-            Err(err) => {
-                log::debug!("Failed to split text: {}", err);
-                Vec::new()
-            }
-        };
-        chunks
+        split_characters(text, self.chunk_size, self.chunk_overlap)
     }
     pub fn delete_record(&mut self, id: &str) -> Result<(), easy_error::Error> {
         let vectors = self.conn.clone();
@@ -65,39 +75,46 @@ impl DeepThoughtVecStore {
         id: &str,
         text: &str,
         embedder: &DeepThoughtModel,
+    ) -> Result<Duration, easy_error::Error> {
+        let splitter = self.splitter;
+        self.add_document_with(id, text, embedder, splitter, &HashMap::new())
+    }
+    pub fn add_document_with_splitter(
+        &mut self,
+        id: &str,
+        text: &str,
+        embedder: &DeepThoughtModel,
+        splitter: DeepThoughtSplitter,
+    ) -> Result<Duration, easy_error::Error> {
+        self.add_document_with(id, text, embedder, splitter, &HashMap::new())
+    }
+    //
+    // Metadata such as source, title or author is stored on every chunk
+    //
+    pub fn add_document_with_metadata(
+        &mut self,
+        id: &str,
+        text: &str,
+        embedder: &DeepThoughtModel,
+        metadata: &HashMap<String, serde_json::Value>,
+    ) -> Result<Duration, easy_error::Error> {
+        let splitter = self.splitter;
+        self.add_document_with(id, text, embedder, splitter, metadata)
+    }
+    //
+    // Chunks and the document manifest stay in memory until save_vectorstore
+    //
+    pub fn add_document_with(
+        &mut self,
+        id: &str,
+        text: &str,
+        embedder: &DeepThoughtModel,
+        splitter: DeepThoughtSplitter,
+        metadata: &HashMap<String, serde_json::Value>,
     ) -> Result<Duration, easy_error::Error> {
         let timer = Timer::new();
-        let chunks: Vec<String> = self.split_text(text);
-        let vectors = self.conn.clone();
-        let mut conn = match vectors.write() {
-            Ok(conn) => conn,
-            Err(err) => bail!("Failed to acquire write lock: {}", err),
-        };
-        let mut n = 0;
-        for c in chunks.iter() {
-            let c_id: &str = &format!("{}-{}", &id, n);
-            let vector = match embedder.embed(&[format!("{} {}", self.embedding_prefix, c)]) {
-                Ok(vector) => vector[0].clone(),
-                Err(err) => bail!("Failed to embed text: {:?}", err),
-            };
-            let mut meta = Metadata {
-                fields: HashMap::new(),
-            };
-            meta.fields.insert("id".into(), serde_json::json!(c_id));
-            meta.fields.insert("n".into(), serde_json::json!(n));
-            meta.fields.insert("text".into(), serde_json::json!(c));
-            match conn.upsert(c_id.into(), vector.to_vec(), meta) {
-                Ok(_) => {}
-                Err(err) => bail!("Failed to add document: {}", err),
-            };
-            match conn.index_text(c_id.into(), c) {
-                Ok(_) => {}
-                Err(err) => bail!("Failed to index document: {}", err),
-            };
-            n += 1;
-        }
-        drop(conn);
-        drop(vectors);
+        let (chunks, vectors) = self.embed_chunks(text, embedder, splitter)?;
+        self.write_chunks(id, text, &chunks, &vectors, metadata)?;
         let t = timer.took();
         let duration = t.as_std();
         Ok(*duration)
@@ -107,6 +124,15 @@ impl DeepThoughtVecStore {
         id: &str,
         text: &str,
         embedder: &DeepThoughtModel,
+    ) -> Result<Duration, easy_error::Error> {
+        self.add_string_with_metadata(id, text, embedder, &HashMap::new())
+    }
+    pub fn add_string_with_metadata(
+        &mut self,
+        id: &str,
+        text: &str,
+        embedder: &DeepThoughtModel,
+        metadata: &HashMap<String, serde_json::Value>,
     ) -> Result<Duration, easy_error::Error> {
         let timer = Timer::new();
         let vectors = self.conn.clone();
@@ -119,7 +145,7 @@ impl DeepThoughtVecStore {
             Err(err) => bail!("Failed to embed text: {:?}", err),
         };
         let mut meta = Metadata {
-            fields: HashMap::new(),
+            fields: metadata.clone(),
         };
         meta.fields.insert("id".into(), serde_json::json!(id));
         meta.fields.insert("n".into(), serde_json::json!(0));
@@ -190,6 +216,39 @@ impl DeepThoughtVecStore {
         &self,
         embedding: Vec<f32>,
         query: &str,
+    ) -> Result<Vec<Neighbor>, easy_error::Error> {
+        self.query_neighbors_k(embedding, query, self.k)
+    }
+    pub fn query_neighbors_filtered(
+        &self,
+        embedding: Vec<f32>,
+        query: &str,
+        filter: &DeepThoughtFilter,
+    ) -> Result<Vec<Neighbor>, easy_error::Error> {
+        // Soft deleted records still take places in the raw results
+        let records = match self.conn.read() {
+            Ok(conn) => conn.active_count() + conn.deleted_count(),
+            Err(err) => bail!("Failed to acquire read lock: {:?}", err),
+        };
+        let mut k = std::cmp::max(1, self.k * FILTER_OVERFETCH);
+        loop {
+            let neighbors: Vec<Neighbor> = self
+                .query_neighbors_k(embedding.clone(), query, k)?
+                .into_iter()
+                .filter(|n| filter.matches(&n.metadata.fields))
+                .take(self.k)
+                .collect();
+            if neighbors.len() >= self.k || k >= records {
+                return Ok(neighbors);
+            }
+            k = k.saturating_mul(2);
+        }
+    }
+    fn query_neighbors_k(
+        &self,
+        embedding: Vec<f32>,
+        query: &str,
+        k: usize,
     ) -> Result<Vec<Neighbor>, easy_error::Error> {
         let conn = self.conn.clone();
         let conn_read = match conn.read() {
@@ -202,7 +261,7 @@ impl DeepThoughtVecStore {
             vector: embedding,
             keywords: query.to_string(),
             filter: None,
-            k: self.k,
+            k,
             alpha: self.alpha,
         };
         let raws_results = match conn_read.hybrid_query(h_query) {
@@ -239,13 +298,30 @@ impl DeepThoughtVecStore {
         embedding: Vec<f32>,
         query: &str,
     ) -> Result<Vec<String>, easy_error::Error> {
-        let mut res: Vec<String> = Vec::new();
         let neighbors = match self.query_neighbors(embedding, query) {
             Ok(neighbors) => neighbors,
             Err(err) => {
                 bail!("Failed to query vector store: {:?}", err);
             }
         };
+        DeepThoughtVecStore::neighbors_text(&neighbors)
+    }
+    pub fn query_filtered(
+        &self,
+        embedding: Vec<f32>,
+        query: &str,
+        filter: &DeepThoughtFilter,
+    ) -> Result<Vec<String>, easy_error::Error> {
+        let neighbors = match self.query_neighbors_filtered(embedding, query, filter) {
+            Ok(neighbors) => neighbors,
+            Err(err) => {
+                bail!("Failed to query vector store: {:?}", err);
+            }
+        };
+        DeepThoughtVecStore::neighbors_text(&neighbors)
+    }
+    fn neighbors_text(neighbors: &[Neighbor]) -> Result<Vec<String>, easy_error::Error> {
+        let mut res: Vec<String> = Vec::new();
         for neighbor in neighbors.iter() {
             match neighbor.metadata.fields.get("text") {
                 Some(text) => {
@@ -306,6 +382,7 @@ impl DeepThoughtVecStore {
         }
         drop(conn_write);
         drop(conn);
-        Ok(())
+        self.save_documents()?;
+        self.save_sources()
     }
 }
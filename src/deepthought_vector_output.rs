@@ -40,6 +40,7 @@ impl DeepThoughtVecStore {
             id => n.id,
             score => n.score,
             text => text_str,
+            metadata => &n.metadata.fields,
         };
         match template.render(&context) {
             Ok(rendered) => res.push_str(&rendered),
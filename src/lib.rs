@@ -1,17 +1,19 @@
 extern crate log;
 
 use rust_rule_engine::{Facts, KnowledgeBase, Rule};
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 
 use llama_cpp_2::{
     ApplyChatTemplateError, ChatTemplateError, DecodeError, EmbeddingsError, LlamaContextLoadError,
-    LlamaCppError, LlamaModelLoadError, LogOptions, NewLlamaChatMessageError, StringToTokenError,
-    TokenToStringError,
+    LlamaCppError, LlamaLoraAdapterInitError, LlamaLoraAdapterSetError, LlamaModelLoadError,
+    LogOptions, NewLlamaChatMessageError, StringToTokenError, TokenToStringError,
+    context::LlamaContext,
     llama_backend::LlamaBackend,
     llama_batch::BatchAddError,
-    model::{LlamaChatMessage, LlamaChatTemplate, LlamaModel},
+    model::{LlamaChatMessage, LlamaChatTemplate, LlamaLoraAdapter, LlamaModel},
+    token::LlamaToken,
 };
 use rust_dynamic::types::*;
 use rust_dynamic::value::Value;
@@ -20,9 +22,23 @@ use vecstore::VecStore;
 pub mod deepthought;
 pub mod deepthought_backend;
 pub mod deepthought_builder;
+pub mod deepthought_compaction;
 pub mod deepthought_context;
 pub mod deepthought_ctx_model;
+pub mod deepthought_documents;
+pub mod deepthought_embed;
+pub mod deepthought_extract;
+pub mod deepthought_filter;
+pub mod deepthought_generation;
+pub mod deepthought_grammar;
+pub mod deepthought_history;
+pub mod deepthought_info;
+pub mod deepthought_ingest;
+pub mod deepthought_kv_cache;
+pub mod deepthought_lora;
+pub mod deepthought_message;
 pub mod deepthought_model;
+pub mod deepthought_params;
 pub mod deepthought_prompt;
 pub mod deepthought_router;
 pub mod deepthought_router_builder;
@@ -37,6 +53,13 @@ pub mod deepthought_router_rag;
 pub mod deepthought_router_route;
 pub mod deepthought_router_sessions;
 pub mod deepthought_router_template;
+pub mod deepthought_sampling;
+pub mod deepthought_session;
+pub mod deepthought_session_store;
+pub mod deepthought_splitter;
+pub mod deepthought_stream;
+pub mod deepthought_sync;
+pub mod deepthought_tokenizer;
 pub mod deepthought_vector;
 pub mod deepthought_vector_output;
 
@@ -48,30 +71,373 @@ type DeepThoughtVector = Arc<RwLock<VecStore>>;
 #[derive(Clone)]
 pub struct DeepThoughtBackend {
     backend: Arc<LlamaBackend>,
+    models: Arc<Mutex<HashMap<DeepThoughtModelKey, Arc<LlamaModel>>>>,
+}
+
+//
+// What a loaded GGUF model is, as reported by llama.cpp
+//
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeepThoughtModelInfo {
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    pub n_params: u64,
+    pub size: u64,
+    pub n_vocab: i32,
+    pub n_ctx_train: u32,
+    pub n_embd: i32,
+    pub chat_template: Option<String>,
+    pub special_tokens: HashMap<String, String>,
+    pub metadata: HashMap<String, String>,
+}
+
+//
+// GGUF LoRA adapter applied with scale to every context created for the model
+//
+pub struct DeepThoughtLoraAdapter {
+    pub path: String,
+    pub scale: f32,
+    adapter: LlamaLoraAdapter,
+}
+
+//
+// GGUF metadata override applied when the model is loaded
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeepThoughtKvOverride {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+//
+// Options passed to LlamaModelParams, None keeps the llama.cpp default
+//
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeepThoughtModelParams {
+    pub use_mmap: Option<bool>,
+    pub use_mlock: Option<bool>,
+    pub vocab_only: Option<bool>,
+    pub kv_overrides: Vec<(String, DeepThoughtKvOverride)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepThoughtKvCacheType {
+    F32,
+    F16,
+    BF16,
+    Q8_0,
+    Q5_1,
+    Q5_0,
+    Q4_1,
+    Q4_0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepThoughtRopeScaling {
+    None,
+    Linear,
+    Yarn,
+}
+
+//
+// Options passed to LlamaContextParams, None keeps the llama.cpp default
+//
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeepThoughtContextParams {
+    pub n_threads: Option<i32>,
+    pub n_threads_batch: Option<i32>,
+    pub flash_attention: Option<bool>,
+    pub type_k: Option<DeepThoughtKvCacheType>,
+    pub type_v: Option<DeepThoughtKvCacheType>,
+    pub rope_scaling: Option<DeepThoughtRopeScaling>,
+    pub rope_freq_base: Option<f32>,
+    pub rope_freq_scale: Option<f32>,
+}
+
+//
+// Loaded GGUF weights are shared between models with the same key
+//
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeepThoughtModelKey {
+    pub path: String,
+    pub params: String,
 }
 
 pub struct DeepThoughtModel {
+    pub id: String,
+    pub model_path: String,
     pub context_length: usize,
     pub batch_size: usize,
     pub registry: DeepThoughtBackend,
-    pub model: LlamaModel,
+    pub model: Arc<LlamaModel>,
+    pub context_options: DeepThoughtContextParams,
+    pub lora_adapters: Vec<DeepThoughtLoraAdapter>,
     pub chat_template: Option<LlamaChatTemplate>,
     pub system_prompt: String,
-    pub messages: Vec<LlamaChatMessage>,
+    pub sampling: SamplingParams,
+    pub messages: Vec<DeepThoughtMessage>,
+    pub token_budget: Option<DeepThoughtTokenBudget>,
+    pub compaction: Option<DeepThoughtCompaction>,
+    pub kv_cache: Option<DeepThoughtKvCache>,
+    pub embed_sequences: usize,
+    summarizer: Option<(DeepThoughtSummarizer, Box<DeepThoughtModel>)>,
+    embedder: Mutex<Option<DeepThoughtEmbedder>>,
+}
+
+//
+// Embedding context holding up to sequences texts per decode. The model keeps
+// one between embed calls and only replaces it when a call needs more sequences.
+//
+pub struct DeepThoughtEmbedder {
+    context: DeepThoughtLiveContext,
+    context_length: usize,
+    sequences: usize,
 }
 
 pub struct DeepThoughtCtxModel {
+    pub id: String,
     pub context_length: usize,
     pub batch_size: usize,
     pub registry: DeepThoughtBackend,
-    pub model: LlamaModel,
+    pub model: Arc<LlamaModel>,
+    pub context_options: DeepThoughtContextParams,
+    pub lora_adapters: Vec<DeepThoughtLoraAdapter>,
     pub chat_template: Option<LlamaChatTemplate>,
     pub system_prompt: String,
+    pub sampling: SamplingParams,
+    summarizer: Option<(DeepThoughtSummarizer, Box<DeepThoughtModel>)>,
+}
+
+//
+// Mirostat sampling mode, replaces top-k/top-p/min-p/typical when enabled
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirostat {
+    Disabled,
+    V1 { tau: f32, eta: f32 },
+    V2 { tau: f32, eta: f32 },
+}
+
+//
+// Sampler chain configuration shared by DeepThoughtModel and DeepThoughtCtxModel
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingParams {
+    pub temperature: f32,
+    pub top_k: Option<i32>,
+    pub top_p: f32,
+    pub min_p: f32,
+    pub typical: f32,
+    pub repeat_last_n: i32,
+    pub repeat_penalty: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+    pub mirostat: Mirostat,
+    pub seed: u32,
+    pub max_new_tokens: Option<usize>,
+    pub stop: Vec<String>,
+    pub grammar: Option<String>,
+}
+
+//
+// GBNF grammar helpers for constrained generation
+//
+pub struct DeepThoughtGrammar;
+
+//
+// Types that can describe themselves with a JSON Schema, used by ask_json()
+//
+pub trait DeepThoughtJsonSchema {
+    fn json_schema() -> serde_json::Value;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepThoughtFinishReason {
+    Eog,
+    StopSequence,
+    MaxTokens,
+    ContextFull,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeepThoughtCompletion {
+    pub text: String,
+    pub finish_reason: DeepThoughtFinishReason,
+}
+
+//
+// io::Write adapter that turns generated bytes into complete UTF-8 pieces
+// and hands each of them to the callback as soon as it is decoded
+//
+pub struct DeepThoughtStream<F: FnMut(&str)> {
+    callback: F,
+    pending: Vec<u8>,
+    text: String,
+}
+
+//
+// Chat history entry, converted into LlamaChatMessage when the template is applied
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeepThoughtMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+//
+// Token budget for chat history: the oldest unpinned non-system messages are
+// dropped until the templated history plus reserve fits into max_tokens
+// (or the model context length)
+//
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DeepThoughtTokenBudget {
+    pub max_tokens: Option<usize>,
+    pub reserve: usize,
+}
+
+//
+// Summarization compaction: once the history takes more than threshold tokens,
+// older turns (all but the last keep_last messages) are replaced by a summary
+// at the end of the system prompt
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeepThoughtCompaction {
+    pub threshold: usize,
+    pub keep_last: usize,
+    pub prompt: String,
+    //
+    // None summarizes with the model holding the conversation
+    //
+    #[serde(default)]
+    pub summarizer: Option<DeepThoughtSummarizer>,
+}
+
+//
+// Model writing compaction summaries. Routes are only known to the router,
+// elsewhere the model holding the conversation summarizes instead. A GGUF
+// model is loaded once and kept by the model it summarizes for.
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeepThoughtSummarizer {
+    Route(String),
+    Model {
+        path: String,
+        #[serde(default)]
+        params: DeepThoughtModelParams,
+    },
+}
+
+//
+// Serialized form of DeepThoughtContext, the KV cache is never persisted
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeepThoughtContextRecord {
+    #[serde(default)]
+    pub max_msg: Option<usize>,
+    #[serde(default)]
+    pub token_budget: Option<DeepThoughtTokenBudget>,
+    #[serde(default)]
+    pub compaction: Option<DeepThoughtCompaction>,
+    #[serde(default)]
+    pub updated: u64,
+    pub messages: Vec<DeepThoughtMessage>,
+}
+
+//
+// Persistent storage for router sessions, keyed by session name
+//
+pub trait SessionStore: Send + Sync {
+    fn save(&self, name: &str, record: &DeepThoughtContextRecord) -> Result<(), easy_error::Error>;
+    fn load(&self, name: &str) -> Result<Option<DeepThoughtContextRecord>, easy_error::Error>;
+    fn delete(&self, name: &str) -> Result<bool, easy_error::Error>;
+    fn names(&self) -> Result<Vec<String>, easy_error::Error>;
+}
+
+//
+// One JSON file per session in a directory
+//
+pub struct DeepThoughtFileSessionStore {
+    path: String,
+}
+
+//
+// Sessions kept in an embedded sled database
+//
+pub struct DeepThoughtSledSessionStore {
+    db: sled::Db,
+}
+
+//
+// Header of a DeepThoughtModel session file, followed by the raw KV state
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeepThoughtSession {
+    pub system_prompt: String,
+    pub messages: Vec<DeepThoughtMessage>,
+    #[serde(default)]
+    pub model_path: String,
+    pub n_vocab: i32,
+    #[serde(default)]
+    pub n_ctx_train: u32,
+    #[serde(default)]
+    pub n_embd: i32,
+    pub tokens: Vec<i32>,
 }
 
+type DeepThoughtLlamaContext<'a> = LlamaContext<'a>;
+
+//
+// llama.cpp context stored together with the weights it borrows
+//
+self_cell::self_cell!(
+    struct DeepThoughtLiveContext {
+        owner: Arc<LlamaModel>,
+
+        #[not_covariant]
+        dependent: DeepThoughtLlamaContext,
+    }
+);
+
+//
+// llama.cpp context kept alive between turns and the tokens decoded into it,
+// a matching prompt prefix is not decoded again. lora_scales are the adapter
+// scales the cached tokens were decoded with.
+//
+pub struct DeepThoughtKvCache {
+    pub owner: String,
+    pub tokens: Vec<LlamaToken>,
+    pub lora_scales: Vec<f32>,
+    context: DeepThoughtLiveContext,
+}
+
+//
+// SAFETY: the context is only reached through &mut self in with_context, and
+// llama.cpp contexts are not bound to the thread that created them. Sharing it
+// behind & is not safe, so the cache is deliberately not Sync.
+//
+unsafe impl Send for DeepThoughtKvCache {}
+
+//
+// SAFETY: as with DeepThoughtKvCache, the context is only reached through
+// &mut self, the model keeps its embedder behind a Mutex
+//
+unsafe impl Send for DeepThoughtEmbedder {}
+
 pub struct DeepThoughtContext {
     max_msg: Option<usize>,
-    messages: Vec<LlamaChatMessage>,
+    token_budget: Option<DeepThoughtTokenBudget>,
+    compaction: Option<DeepThoughtCompaction>,
+    messages: Vec<DeepThoughtMessage>,
+    kv_cache: Option<DeepThoughtKvCache>,
 }
 
 pub struct DeepThought {
@@ -86,7 +452,12 @@ pub struct DeepThought {
 
 pub struct DeepThoughtRouter {
     sessions: HashMap<String, DeepThoughtContext>,
+    session_store: Option<Box<dyn SessionStore>>,
+    session_ttl: Option<std::time::Duration>,
+    embedding_dimension: Option<usize>,
     backend: DeepThoughtBackend,
+    model_params: DeepThoughtModelParams,
+    context_params: DeepThoughtContextParams,
     prompt_model: Option<DeepThoughtCtxModel>,
     embed_model: Option<DeepThoughtModel>,
     routes: HashMap<String, DeepThought>,
@@ -107,6 +478,12 @@ pub struct DeepThoughtRouterBuilder {
     embedding_query_prefix: String,
     default_embed_model: Option<String>,
     query_preference: Option<String>,
+    session_dir: Option<String>,
+    session_db: Option<String>,
+    session_ttl: Option<std::time::Duration>,
+    model_params: Option<DeepThoughtModelParams>,
+    context_params: Option<DeepThoughtContextParams>,
+    embedding_dimension: Option<usize>,
 }
 
 pub struct DeepThoughtBuilder {
@@ -122,13 +499,166 @@ pub struct DeepThoughtBuilder {
     alpha: f32,
     k: usize,
     max_score: f32,
+    splitter: Option<DeepThoughtSplitter>,
+    sampling: Option<SamplingParams>,
+    model_params: Option<DeepThoughtModelParams>,
+    context_params: Option<DeepThoughtContextParams>,
+    lora_adapters: Vec<(String, f32)>,
+    embedding_dimension: Option<usize>,
+}
+
+//
+// How documents are cut into chunks before embedding
+//
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeepThoughtSplitter {
+    // chunk_size and chunk_overlap in characters
+    #[default]
+    Characters,
+    // chunk_size and chunk_overlap in embedder tokens
+    Tokens,
+    // Sections by heading hierarchy, heading_path stored in metadata
+    Markdown,
+    // Function, class and type definitions, language and line_start in metadata
+    Code(DeepThoughtLanguage),
+    // Whole sentences packed up to chunk_size characters
+    Sentences,
+    // Whole lines packed up to chunk_size characters, line_start and line_end in metadata
+    Lines,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepThoughtLanguage {
+    Rust,
+    Python,
+    JavaScript,
+    Go,
+    Java,
+    C,
+}
+
+//
+// Piece of a document stored as a single vector, metadata is merged into the record
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepThoughtChunk {
+    pub text: String,
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+//
+// HTML or Markdown page rendered as Markdown before splitting, title comes
+// from <title> or the first level 1 heading
+//
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeepThoughtExtracted {
+    pub title: Option<String>,
+    pub markdown: String,
+}
+
+//
+// File types ingest_path extracts text from, detected by extension
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepThoughtFileType {
+    Text,
+    Markdown,
+    Html,
+    Json,
+    JsonLines,
+    Csv,
+    Code(DeepThoughtLanguage),
+}
+
+//
+// Files ingest_path picks up, glob patterns are matched against paths relative
+// to the ingested directory, empty include takes every supported file
+//
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeepThoughtIngestOptions {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub max_file_size: Option<u64>,
+    pub follow_links: bool,
+    pub force: bool,
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeepThoughtIngestStatus {
+    Added,
+    Updated,
+    Unchanged,
+    Removed,
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepThoughtIngestResult {
+    pub path: String,
+    pub status: DeepThoughtIngestStatus,
+    pub doc_id: Option<String>,
+    pub chunks: usize,
+    pub duration: std::time::Duration,
+}
+
+//
+// Files a walk over an ingested path saw and directories it failed to read
+//
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct DeepThoughtWalk {
+    pub results: Vec<DeepThoughtIngestResult>,
+    pub seen: HashSet<String>,
+    pub failed: Vec<String>,
+}
+
+//
+// Outcome of sync_sources, with dry_run nothing was embedded or deleted
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepThoughtSyncReport {
+    pub dry_run: bool,
+    pub results: Vec<DeepThoughtIngestResult>,
+    pub duration: std::time::Duration,
+}
+
+//
+// Entry of the source manifest, built from the documents ingest_path added
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepThoughtSource {
+    pub source: String,
+    pub content_hash: String,
+    pub doc_id: String,
+    pub chunks: Vec<String>,
+    pub updated: u64,
+}
+
+//
+// Chunk ids a document was split into, the document id is returned by add_document.
+// The text is what was split, documents from older manifests have none.
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeepThoughtDocument {
+    pub id: String,
+    pub chunks: Vec<String>,
+    pub updated: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
 }
 
 pub struct DeepThoughtVecStore {
     pub path: Option<String>,
     pub conn: DeepThoughtVector,
+    documents: HashMap<String, DeepThoughtDocument>,
+    sources: HashMap<String, DeepThoughtIngestOptions>,
     chunk_size: usize,
     chunk_overlap: usize,
+    splitter: DeepThoughtSplitter,
     k: usize,
     alpha: f32,
     max_score: f32,
@@ -146,6 +676,31 @@ pub struct DeepThoughtRecommededPrompt {
     pub quick_tests: Vec<String>,
 }
 
+//
+// Condition on chunk metadata checked against query results, date ranges take
+// RFC 3339 timestamps, YYYY-MM-DD dates or unix seconds
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeepThoughtFilter {
+    Eq(String, serde_json::Value),
+    Ne(String, serde_json::Value),
+    In(String, Vec<serde_json::Value>),
+    Range {
+        field: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    DateRange {
+        field: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    Exists(String),
+    And(Vec<DeepThoughtFilter>),
+    Or(Vec<DeepThoughtFilter>),
+    Not(Box<DeepThoughtFilter>),
+}
+
 pub struct VecStoreNeighbors {
     pub id: String,
     pub score: f32,
@@ -240,6 +795,18 @@ impl From<EmbeddingsError> for Error {
     }
 }
 
+impl From<LlamaLoraAdapterInitError> for Error {
+    fn from(value: LlamaLoraAdapterInitError) -> Self {
+        Self::InternalNativeError(value.to_string())
+    }
+}
+
+impl From<LlamaLoraAdapterSetError> for Error {
+    fn from(value: LlamaLoraAdapterSetError) -> Self {
+        Self::InternalNativeError(value.to_string())
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
         Self::IoError(value.to_string())
@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_compaction::SUMMARY_PREFIX;
+    use deepthought::{
+        DeepThoughtCompaction, DeepThoughtContextRecord, DeepThoughtKvOverride, DeepThoughtMessage,
+        DeepThoughtModelParams, DeepThoughtSummarizer,
+    };
+
+    #[test]
+    fn test_compaction_replaces_older_turns() {
+        let mut messages = vec![
+            DeepThoughtMessage::system("you are robot"),
+            DeepThoughtMessage::user("remember violet apples").pinned(),
+            DeepThoughtMessage::user("one"),
+            DeepThoughtMessage::assistant("two"),
+            DeepThoughtMessage::user("three"),
+            DeepThoughtMessage::assistant("four"),
+        ];
+        let compaction = DeepThoughtCompaction::new(10).keep_last(2);
+        let selected = compaction.select(&messages);
+        assert_eq!(selected, vec![2, 3]);
+        let transcript = DeepThoughtCompaction::transcript(&messages, &selected);
+        assert_eq!(transcript, "user: one\n\nassistant: two\n\n");
+        DeepThoughtCompaction::apply(&mut messages, &selected, "counted to two");
+        assert_eq!(messages.len(), 4);
+        assert_eq!(
+            DeepThoughtCompaction::summary(&messages[0]),
+            Some("counted to two")
+        );
+        assert!(messages[0].content.starts_with("you are robot\n\n"));
+        assert!(messages[0].content.ends_with("counted to two"));
+        assert!(messages[1].pinned);
+        assert_eq!(messages[2].content, "three");
+    }
+
+    #[test]
+    fn test_compaction_folds_previous_summary() {
+        let mut messages = vec![
+            DeepThoughtMessage::system("you are robot"),
+            DeepThoughtMessage::user("one"),
+            DeepThoughtMessage::assistant("two"),
+        ];
+        let compaction = DeepThoughtCompaction::new(10).keep_last(0);
+        let selected = compaction.select(&messages);
+        DeepThoughtCompaction::apply(&mut messages, &selected, "counted to two");
+        assert!(compaction.select(&messages).is_empty());
+        messages.push(DeepThoughtMessage::user("three"));
+        let selected = compaction.select(&messages);
+        assert_eq!(selected, vec![1]);
+        let transcript = DeepThoughtCompaction::transcript(&messages, &selected);
+        assert!(transcript.contains("counted to two"));
+        DeepThoughtCompaction::apply(&mut messages, &selected, "counted to three");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].content,
+            format!("you are robot\n\n{}\ncounted to three", SUMMARY_PREFIX)
+        );
+    }
+
+    #[test]
+    fn test_compaction_renders_with_single_system_template() {
+        // Mistral and Gemma templates reject a system message past the first one
+        let template = r#"{% for message in messages %}{% if message.role == "system" and not loop.first %}{{ raise_exception("system message must be first") }}{% endif %}[{{ message.role }}] {{ message.content }}
+{% endfor %}"#;
+        let mut env = minijinja::Environment::new();
+        env.add_function(
+            "raise_exception",
+            |msg: String| -> Result<String, minijinja::Error> {
+                Err(minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    msg,
+                ))
+            },
+        );
+        env.add_template("chat", template).unwrap();
+        let mut messages = vec![
+            DeepThoughtMessage::system("you are robot"),
+            DeepThoughtMessage::user("one"),
+            DeepThoughtMessage::assistant("two"),
+            DeepThoughtMessage::user("three"),
+        ];
+        let compaction = DeepThoughtCompaction::new(10).keep_last(1);
+        let selected = compaction.select(&messages);
+        DeepThoughtCompaction::apply(&mut messages, &selected, "counted to two");
+        let rendered = env
+            .get_template("chat")
+            .unwrap()
+            .render(minijinja::context! { messages => messages })
+            .unwrap();
+        assert!(rendered.starts_with("[system] you are robot"));
+        assert!(rendered.contains("counted to two"));
+        assert!(rendered.ends_with("[user] three\n"));
+    }
+
+    #[test]
+    fn test_compaction_without_system_prompt() {
+        let mut messages = vec![
+            DeepThoughtMessage::user("one"),
+            DeepThoughtMessage::assistant("two"),
+        ];
+        let compaction = DeepThoughtCompaction::new(10).keep_last(0);
+        let selected = compaction.select(&messages);
+        DeepThoughtCompaction::apply(&mut messages, &selected, "counted to two");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(
+            messages[0].content,
+            format!("{}\ncounted to two", SUMMARY_PREFIX)
+        );
+    }
+
+    #[test]
+    fn test_compaction_summarizer() {
+        let compaction = DeepThoughtCompaction::new(10);
+        assert_eq!(compaction.summarizer, None);
+        let compaction = compaction.summarizer(DeepThoughtSummarizer::route("summary"));
+        assert_eq!(
+            compaction.summarizer,
+            Some(DeepThoughtSummarizer::Route("summary".to_string()))
+        );
+        let record: DeepThoughtContextRecord = serde_json::from_str(
+            r#"{"compaction":{"threshold":10,"keep_last":2,"prompt":"summarize"},"messages":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(record.compaction.unwrap().summarizer, None);
+    }
+
+    #[test]
+    fn test_compaction_summarizer_model_params() {
+        let params = DeepThoughtModelParams::new().use_mmap(false).kv_override(
+            "tokenizer.ggml.add_bos_token",
+            DeepThoughtKvOverride::Bool(true),
+        );
+        let compaction = DeepThoughtCompaction::new(10).summarizer(
+            DeepThoughtSummarizer::model_with_params("summarizer.gguf", &params),
+        );
+        let json = serde_json::to_string(&compaction).unwrap();
+        let restored: DeepThoughtCompaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, compaction);
+        let compaction: DeepThoughtCompaction = serde_json::from_str(
+            r#"{"threshold":10,"keep_last":2,"prompt":"summarize","summarizer":{"Model":{"path":"summarizer.gguf"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            compaction.summarizer,
+            Some(DeepThoughtSummarizer::model("summarizer.gguf"))
+        );
+    }
+}
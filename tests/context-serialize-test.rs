@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThoughtContext, DeepThoughtMessage};
+
+    #[test]
+    fn test_context_json_roundtrip() {
+        let mut dtc = DeepThoughtContext::init("you are robot").unwrap();
+        dtc.maximum_messages(10);
+        dtc.push(DeepThoughtMessage::user("hello").with_metadata("user", serde_json::json!("joe")))
+            .unwrap();
+        dtc.assistant("hi").unwrap();
+        let data = dtc.to_json().unwrap();
+        let restored = DeepThoughtContext::from_json(&data).unwrap();
+        assert_eq!(restored.messages(), dtc.messages());
+        assert_eq!(restored.messages()[1].metadata["user"], "joe");
+    }
+
+    #[test]
+    fn test_context_openai_import() {
+        let request = serde_json::json!({
+            "model": "any",
+            "messages": [
+                {"role": "developer", "content": "you are robot"},
+                {"role": "user", "content": [{"type": "text", "text": "hello"}]},
+                {"role": "assistant", "content": "hi"}
+            ]
+        });
+        let dtc = DeepThoughtContext::from_openai(&request).unwrap();
+        assert_eq!(dtc.messages().len(), 3);
+        assert_eq!(dtc.messages()[0].role, "system");
+        assert_eq!(dtc.messages()[1].content, "hello");
+        assert_eq!(
+            dtc.to_openai()[2],
+            serde_json::json!({"role": "assistant", "content": "hi"})
+        );
+    }
+}
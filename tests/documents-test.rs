@@ -0,0 +1,119 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_documents::join_chunks;
+    use deepthought::{DeepThoughtBackend, DeepThoughtModel, DeepThoughtVecStore};
+
+    fn test_store(name: &str) -> (std::path::PathBuf, DeepThoughtVecStore) {
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-documents-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let store = DeepThoughtVecStore::new(&path.display().to_string()).unwrap();
+        (path, store)
+    }
+
+    fn test_embedder() -> DeepThoughtModel {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        dtb.load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_join_chunks_drops_overlap() {
+        let chunks = vec![
+            "The quick brown fox jumps over the lazy dog".to_string(),
+            "jumps over the lazy dog and runs away".to_string(),
+        ];
+        assert_eq!(
+            join_chunks(&chunks),
+            "The quick brown fox jumps over the lazy dog and runs away"
+        );
+    }
+
+    #[test]
+    fn test_join_chunks_without_overlap() {
+        let chunks = vec!["# Title\nIntro".to_string(), "Other section".to_string()];
+        assert_eq!(join_chunks(&chunks), "# Title\nIntro\nOther section");
+        assert_eq!(join_chunks(&[]), "");
+    }
+
+    #[test]
+    fn test_delete_document() {
+        let embedder = test_embedder();
+        let (path, mut store) = test_store("delete");
+        store
+            .add_document("doc", "The quick brown fox", &embedder)
+            .unwrap();
+        assert!(store.has_document("doc"));
+        assert_eq!(store.get_document("doc").unwrap(), "The quick brown fox");
+        assert!(store.delete_document("doc").unwrap());
+        assert!(!store.has_document("doc"));
+        assert!(store.chunk_ids("doc").is_empty());
+        assert!(store.get_document("doc").is_err());
+        assert!(!store.delete_document("doc").unwrap());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_replace_document() {
+        let embedder = test_embedder();
+        let (path, mut store) = test_store("replace");
+        assert!(
+            store
+                .replace_document("doc", "Nothing to replace", &embedder)
+                .is_err()
+        );
+        store
+            .add_document("doc", "The quick brown fox", &embedder)
+            .unwrap();
+        store
+            .replace_document("doc", "The lazy dog", &embedder)
+            .unwrap();
+        assert_eq!(store.get_document("doc").unwrap(), "The lazy dog");
+        assert_eq!(store.chunk_ids("doc"), vec!["doc-0".to_string()]);
+        assert_eq!(store.list_documents(), vec!["doc".to_string()]);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_get_document_returns_added_text() {
+        let embedder = test_embedder();
+        let (path, mut store) = test_store("text");
+        // Repeated paragraphs make chunk overlaps impossible to tell from real text
+        let text = "The quick brown fox jumps over the lazy dog.\n\n".repeat(80);
+        store.add_document("doc", &text, &embedder).unwrap();
+        assert!(store.chunk_ids("doc").len() > 1);
+        assert_eq!(store.get_document("doc").unwrap(), text);
+        store.save_vectorstore().unwrap();
+        drop(store);
+        let store = DeepThoughtVecStore::new(&path.display().to_string()).unwrap();
+        assert_eq!(store.get_document("doc").unwrap(), text);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_document_changes_written_by_save() {
+        let embedder = test_embedder();
+        let (path, mut store) = test_store("save");
+        let dbpath = path.display().to_string();
+        store
+            .add_document("doc", "The quick brown fox", &embedder)
+            .unwrap();
+        store.save_vectorstore().unwrap();
+        assert!(store.delete_document("doc").unwrap());
+        drop(store);
+        let mut store = DeepThoughtVecStore::new(&dbpath).unwrap();
+        assert!(store.has_document("doc"));
+        assert!(store.delete_document("doc").unwrap());
+        store.save_vectorstore().unwrap();
+        drop(store);
+        let store = DeepThoughtVecStore::new(&dbpath).unwrap();
+        assert!(!store.has_document("doc"));
+        assert!(store.list_documents().is_empty());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
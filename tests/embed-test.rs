@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::DeepThoughtBackend;
+    use deepthought::deepthought_embed::{embed_batches, embed_sequence_length};
+
+    #[test]
+    fn test_embed_batches_by_sequences() {
+        assert_eq!(
+            embed_batches(&[1, 1, 1, 1, 1], 100, 2),
+            vec![(0, 2), (2, 4), (4, 5)]
+        );
+    }
+
+    #[test]
+    fn test_embed_batches_by_tokens() {
+        assert_eq!(
+            embed_batches(&[6, 5, 3, 10], 10, 16),
+            vec![(0, 1), (1, 3), (3, 4)]
+        );
+        assert!(embed_batches(&[], 10, 16).is_empty());
+    }
+
+    #[test]
+    fn test_embed_sequence_length() {
+        assert_eq!(embed_sequence_length(2048 * 16, 16), 2048);
+        assert_eq!(embed_sequence_length(2048, 0), 2048);
+    }
+
+    #[test]
+    fn test_embed_chunk_close_to_context_length() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let dtm = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let limit = dtm.context_length - 8;
+        let text = dtm
+            .truncate_to_tokens(&"deep thought ".repeat(dtm.context_length), limit)
+            .unwrap();
+        assert!(dtm.count_tokens(&text).unwrap() >= limit - 2);
+        let emb = dtm.embed_batch(&[text.clone(), text]).unwrap();
+        assert_eq!(emb.len(), 2);
+        assert_eq!(emb[0].len(), 768);
+    }
+
+    #[test]
+    fn test_embedder_reuses_context() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let dtm = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let mut embedder = dtm.embedder(2).unwrap();
+        assert_eq!(embedder.n_ctx(), dtm.context_length * 2);
+        let first = embedder.embed_batch(&["deep thought", "answer"]).unwrap();
+        let second = embedder.embed_batch(&["deep thought"]).unwrap();
+        assert_eq!(embedder.n_ctx(), dtm.context_length * 2);
+        let similarity: f32 = first[0]
+            .iter()
+            .zip(second[0].iter())
+            .map(|(a, b)| a * b)
+            .sum();
+        assert!(similarity > 0.999);
+        assert_eq!(dtm.embedder(100).unwrap().sequences(), dtm.embed_sequences);
+    }
+
+    #[test]
+    fn test_embed_sizes_context_by_texts() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let dtm = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        assert_eq!(dtm.embedder_sequences(), None);
+        dtm.embed(&["deep thought"]).unwrap();
+        assert_eq!(dtm.embedder_sequences(), Some(1));
+        dtm.embed_batch(&["deep", "thought", "answer"]).unwrap();
+        assert_eq!(dtm.embedder_sequences(), Some(3));
+        // A smaller call keeps the larger context
+        dtm.embed(&["deep thought"]).unwrap();
+        assert_eq!(dtm.embedder_sequences(), Some(3));
+    }
+}
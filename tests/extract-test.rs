@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_extract::{html_to_markdown, markdown_title, strip_markdown};
+    use deepthought::deepthought_splitter::heading_anchor;
+    use deepthought::{
+        DeepThoughtBackend, DeepThoughtExtracted, DeepThoughtSplitter, DeepThoughtVecStore,
+    };
+    use std::collections::HashMap;
+
+    const PAGE: &str = r#"<html><head><title>Wiki Page</title></head><body>
+<nav><a href="/">Home</a></nav>
+<h1 id="top">Install</h1>
+<p>Run the <b>installer</b> and <a href="/docs">read the docs</a>.</p>
+<script>alert(1)</script>
+<ul><li>One</li><li>Two<ol><li>Nested</li></ol></li></ul>
+<h2>Config</h2>
+<table><tr><th>Key</th><th>Value</th></tr><tr><td>port</td><td>80</td></tr></table>
+</body></html>"#;
+
+    #[test]
+    fn test_html_to_markdown() {
+        assert_eq!(
+            html_to_markdown(PAGE),
+            "# Install {#top}\n\nRun the installer and read the docs.\n\n- One\n- Two\n  1. Nested\n\n## Config\n\n| Key | Value |\n| --- | --- |\n| port | 80 |"
+        );
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        assert_eq!(
+            strip_markdown(
+                "# Guide {#guide}\n\nSome **bold** `snake_case` [link](http://x).\n\n* item\n\n| a | b |\n|---|---|\n| 1 | 2 |"
+            ),
+            "Guide\n\nSome bold snake_case link.\n\n- item\n\na | b\n1 | 2"
+        );
+        assert_eq!(
+            markdown_title("Intro\n\n# Guide {#guide}\n"),
+            Some("Guide".to_string())
+        );
+        assert_eq!(
+            heading_anchor("Getting Started"),
+            ("Getting Started".to_string(), "getting-started".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extracted_chunks() {
+        let extracted = DeepThoughtExtracted::from_html(PAGE);
+        assert_eq!(extracted.title, Some("Wiki Page".to_string()));
+        let chunks = extracted.chunks(1000, 0);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].metadata["anchor"], "top");
+        assert_eq!(chunks[1].metadata["anchor"], "config");
+        assert_eq!(chunks[1].metadata["heading_path"], "Install > Config");
+        assert_eq!(chunks[1].text, "Config\n\nKey | Value\nport | 80");
+    }
+
+    #[test]
+    fn test_add_markdown_with_token_splitter() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let embedder = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("deepthought-extract-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut store = DeepThoughtVecStore::new(&path.display().to_string()).unwrap();
+        store.set_splitter(DeepThoughtSplitter::Tokens);
+        let markdown = format!(
+            "# Install\n\n{}\n\n## Config\n\nport 80",
+            "the quick brown fox jumps over the lazy dog ".repeat(300)
+        );
+        store
+            .add_markdown("page", &markdown, &embedder, &HashMap::new())
+            .unwrap();
+        let limit = store.max_chunk_tokens(&embedder).unwrap();
+        let ids = store.chunk_ids("page");
+        assert!(ids.len() > 2);
+        for id in ids.iter() {
+            let text = store.get(id).unwrap();
+            assert!(embedder.count_tokens(&text).unwrap() <= limit);
+        }
+        assert!(store.get(ids.last().unwrap()).unwrap().contains("port 80"));
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
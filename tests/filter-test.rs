@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::DeepThoughtFilter;
+    use deepthought::deepthought_filter::parse_timestamp;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn metadata() -> HashMap<String, serde_json::Value> {
+        let mut metadata = HashMap::new();
+        metadata.insert("tag.type".to_string(), json!("route"));
+        metadata.insert("tenant".to_string(), json!({"id": "acme"}));
+        metadata.insert("n".to_string(), json!(3));
+        metadata.insert("created_at".to_string(), json!("2024-03-10T12:00:00Z"));
+        metadata
+    }
+
+    #[test]
+    fn test_filter_equality_and_membership() {
+        let metadata = metadata();
+        assert!(DeepThoughtFilter::equals("tag.type", "route").matches(&metadata));
+        assert!(DeepThoughtFilter::equals("tenant.id", "acme").matches(&metadata));
+        assert!(DeepThoughtFilter::equals("n", 3.0).matches(&metadata));
+        assert!(!DeepThoughtFilter::equals("tag.type", "url").matches(&metadata));
+        assert!(
+            DeepThoughtFilter::one_of("tag.type", vec![json!("url"), json!("route")])
+                .matches(&metadata)
+        );
+    }
+
+    #[test]
+    fn test_filter_ranges() {
+        let metadata = metadata();
+        assert!(DeepThoughtFilter::range("n", Some(1.0), Some(3.0)).matches(&metadata));
+        assert!(!DeepThoughtFilter::range("n", Some(4.0), None).matches(&metadata));
+        assert!(
+            DeepThoughtFilter::date_range("created_at", Some("2024-03-01"), Some("2024-03-31"))
+                .matches(&metadata)
+        );
+        assert!(
+            !DeepThoughtFilter::date_range("created_at", Some("2024-03-11"), None)
+                .matches(&metadata)
+        );
+        // A date-only upper bound takes in the whole day
+        assert!(
+            DeepThoughtFilter::date_range("created_at", None, Some("2024-03-10"))
+                .matches(&metadata)
+        );
+        assert!(
+            !DeepThoughtFilter::date_range("created_at", None, Some("2024-03-10T11:00:00Z"))
+                .matches(&metadata)
+        );
+    }
+
+    #[test]
+    fn test_filter_combinations() {
+        let metadata = metadata();
+        let filter = DeepThoughtFilter::equals("tag.type", "route")
+            .and(DeepThoughtFilter::exists("missing").negate());
+        assert!(filter.matches(&metadata));
+        let filter = DeepThoughtFilter::equals("tag.type", "url")
+            .or(DeepThoughtFilter::equals("tenant.id", "acme"));
+        assert!(filter.matches(&metadata));
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp(&json!("1970-01-02")), Some(86400));
+        assert_eq!(
+            parse_timestamp(&json!("2000-03-01T00:00:00Z")),
+            Some(951868800)
+        );
+        assert_eq!(
+            parse_timestamp(&json!("2000-03-01T02:00:00.5+02:00")),
+            Some(951868800)
+        );
+        assert_eq!(parse_timestamp(&json!(42)), Some(42));
+        assert_eq!(parse_timestamp(&json!("yesterday")), None);
+        assert_eq!(parse_timestamp(&json!("2024-03-00")), None);
+        assert_eq!(parse_timestamp(&json!("2024-03-32")), None);
+        assert_eq!(parse_timestamp(&json!("2024-03-10T12-00")), None);
+    }
+}
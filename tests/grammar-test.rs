@@ -0,0 +1,31 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThoughtGrammar, DeepThoughtRecommededPrompt};
+
+    #[test]
+    fn test_grammar_from_recommended_prompt() {
+        let grammar = DeepThoughtGrammar::from_type::<DeepThoughtRecommededPrompt>().unwrap();
+        assert!(grammar.starts_with("root-clarifying-questions ::= "));
+        assert!(grammar.contains("\nroot ::= \"{\" ws \"\\\"clarifying_questions\\\"\" ws"));
+        assert!(grammar.contains("\nstring ::= "));
+    }
+
+    #[test]
+    fn test_grammar_enum_and_optional() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "mode": { "enum": ["fast", "slow"] },
+                "level": { "type": ["integer", "null"] }
+            },
+            "required": ["mode"]
+        });
+        let grammar = DeepThoughtGrammar::from_json_schema(&schema).unwrap();
+        assert!(grammar.starts_with(
+            "root ::= \"{\" ws \"\\\"mode\\\"\" ws \":\" ws ( \"\\\"fast\\\"\" ws | \"\\\"slow\\\"\" ws ) ( \",\" ws \"\\\"level\\\"\" ws \":\" ws ( integer | null ) )? \"}\" ws\n"
+        ));
+        assert!(DeepThoughtGrammar::from_json_schema(&serde_json::json!({ "$ref": "#/a" })).is_err());
+    }
+}
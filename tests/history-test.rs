@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThoughtContext, DeepThoughtMessage, DeepThoughtTokenBudget, Error};
+
+    fn count_words(messages: &[DeepThoughtMessage]) -> Result<usize, Error> {
+        Ok(messages
+            .iter()
+            .map(|msg| msg.content.split_whitespace().count())
+            .sum())
+    }
+
+    #[test]
+    fn test_budget_drops_oldest_turns() {
+        let mut messages = vec![
+            DeepThoughtMessage::system("you are robot"),
+            DeepThoughtMessage::user("remember violet apples").pinned(),
+            DeepThoughtMessage::user("one two three"),
+            DeepThoughtMessage::assistant("four five six"),
+            DeepThoughtMessage::user("seven eight"),
+        ];
+        let budget = DeepThoughtTokenBudget::new(100, 90);
+        let dropped = budget.fit(&mut messages, 4096, count_words).unwrap();
+        assert_eq!(dropped, 2);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].role, "system");
+        assert!(messages[1].pinned);
+        assert_eq!(messages[2].content, "seven eight");
+    }
+
+    #[test]
+    fn test_budget_cannot_fit() {
+        let mut messages = vec![
+            DeepThoughtMessage::system("a very long system prompt"),
+            DeepThoughtMessage::user("question"),
+        ];
+        let budget = DeepThoughtTokenBudget::new(4, 0);
+        assert!(budget.fit(&mut messages, 4096, count_words).is_err());
+    }
+
+    #[test]
+    fn test_context_sliding_window() {
+        let mut ctx = DeepThoughtContext::init("you are robot").unwrap();
+        ctx.maximum_messages(3);
+        ctx.user("first").unwrap();
+        ctx.assistant("second").unwrap();
+        ctx.user("third").unwrap();
+        assert_eq!(ctx.messages().len(), 3);
+        assert_eq!(ctx.messages()[0].role, "system");
+        assert_eq!(ctx.messages()[1].content, "second");
+    }
+}
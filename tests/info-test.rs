@@ -0,0 +1,35 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::DeepThoughtModelInfo;
+    use std::collections::HashMap;
+
+    fn info(n_ctx_train: u32, n_embd: i32) -> DeepThoughtModelInfo {
+        DeepThoughtModelInfo {
+            architecture: Some("llama".to_string()),
+            name: Some("test".to_string()),
+            n_params: 0,
+            size: 0,
+            n_vocab: 32000,
+            n_ctx_train,
+            n_embd,
+            chat_template: None,
+            special_tokens: HashMap::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_info_context_length() {
+        assert!(info(4096, 768).check_context_length(4096).is_ok());
+        assert!(info(4096, 768).check_context_length(8192).is_err());
+        assert!(info(0, 768).check_context_length(8192).is_ok());
+    }
+
+    #[test]
+    fn test_info_embedding_dimension() {
+        assert!(info(4096, 768).check_embedding_dimension(768).is_ok());
+        assert!(info(4096, 768).check_embedding_dimension(1024).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_extract::html_to_text;
+    use deepthought::deepthought_ingest::{content_hash, csv_to_text, json_to_text, source_doc_id};
+    use deepthought::deepthought_sync::source_removed;
+    use deepthought::{
+        DeepThoughtBackend, DeepThoughtFileType, DeepThoughtIngestOptions, DeepThoughtIngestResult,
+        DeepThoughtIngestStatus, DeepThoughtLanguage, DeepThoughtSplitter, DeepThoughtSyncReport,
+        DeepThoughtVecStore,
+    };
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    #[test]
+    fn test_file_type_from_path() {
+        assert_eq!(
+            DeepThoughtFileType::from_path(Path::new("docs/README.MD")),
+            Some(DeepThoughtFileType::Markdown)
+        );
+        assert_eq!(
+            DeepThoughtFileType::from_path(Path::new("src/main.rs")),
+            Some(DeepThoughtFileType::Code(DeepThoughtLanguage::Rust))
+        );
+        assert_eq!(DeepThoughtFileType::from_path(Path::new("image.png")), None);
+        assert_eq!(
+            DeepThoughtFileType::Csv.splitter(DeepThoughtSplitter::Characters),
+            DeepThoughtSplitter::Lines
+        );
+    }
+
+    #[test]
+    fn test_extract_structured_text() {
+        assert_eq!(
+            json_to_text(r#"{"name": "deepthought", "tags": ["rag", 1], "none": null}"#).unwrap(),
+            "name: deepthought\ntags[0]: rag\ntags[1]: 1"
+        );
+        assert_eq!(
+            csv_to_text("name,note\nalpha,\"one, two\"\nbeta,\"say \"\"hi\"\"\"\n"),
+            "name: alpha; note: one, two\nname: beta; note: say \"hi\""
+        );
+        assert_eq!(
+            html_to_text(
+                "<html><script>var x = 1;</script><p>Fish &amp; chips</p><p>Tea</p></html>"
+            ),
+            "Fish & chips\n\nTea"
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(source_doc_id("/tmp/a.txt"), source_doc_id("/tmp/a.txt"));
+        assert_ne!(source_doc_id("/tmp/a.txt"), source_doc_id("/tmp/b.txt"));
+    }
+
+    fn result(path: &str, status: DeepThoughtIngestStatus) -> DeepThoughtIngestResult {
+        DeepThoughtIngestResult {
+            path: path.to_string(),
+            status,
+            doc_id: None,
+            chunks: 0,
+            duration: std::time::Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_sync_report() {
+        let report = DeepThoughtSyncReport {
+            dry_run: true,
+            results: vec![
+                result("a.md", DeepThoughtIngestStatus::Unchanged),
+                result("b.md", DeepThoughtIngestStatus::Removed),
+                result(
+                    "c.bin",
+                    DeepThoughtIngestStatus::Skipped("unsupported".to_string()),
+                ),
+            ],
+            duration: std::time::Duration::ZERO,
+        };
+        assert!(report.has_changes());
+        assert_eq!(
+            report.paths(&DeepThoughtIngestStatus::Removed),
+            vec!["b.md"]
+        );
+        assert_eq!(
+            report.count(&DeepThoughtIngestStatus::Skipped(String::new())),
+            1
+        );
+    }
+
+    #[test]
+    fn test_source_removed_keeps_unreadable_directories() {
+        let roots = vec!["/data/wiki".to_string()];
+        let seen: HashSet<String> = ["/data/wiki/a.md".to_string()].into_iter().collect();
+        let failed = vec!["/data/wiki/locked".to_string()];
+        assert!(!source_removed("/data/wiki/a.md", &roots, &seen, &failed));
+        assert!(!source_removed(
+            "/data/wiki/locked/b.md",
+            &roots,
+            &seen,
+            &failed
+        ));
+        assert!(source_removed("/data/wiki/gone.md", &roots, &seen, &failed));
+        assert!(!source_removed("/data/other/c.md", &roots, &seen, &failed));
+    }
+
+    fn statuses(results: &[DeepThoughtIngestResult]) -> Vec<(String, DeepThoughtIngestStatus)> {
+        results
+            .iter()
+            .map(|res| {
+                let name = Path::new(&res.path).file_name().unwrap();
+                (name.to_string_lossy().to_string(), res.status.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ingest_path_walk() {
+        let dir = std::env::temp_dir().join(format!("deepthought-ingest-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("docs/skip")).unwrap();
+        std::fs::write(dir.join("docs/a.md"), "# Title\nThe quick brown fox").unwrap();
+        std::fs::write(dir.join("docs/b.txt"), "The lazy dog").unwrap();
+        std::fs::write(dir.join("docs/big.txt"), "x".repeat(200)).unwrap();
+        std::fs::write(dir.join("docs/skip/c.txt"), "Excluded").unwrap();
+        std::fs::write(dir.join("docs/d.rs"), "fn main() {}").unwrap();
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let embedder = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let mut store = DeepThoughtVecStore::new(&dir.join("store").display().to_string()).unwrap();
+        let root = dir.join("docs").display().to_string();
+        let options = DeepThoughtIngestOptions::new()
+            .include("*.md")
+            .include("*.txt")
+            .exclude("skip/**")
+            .max_file_size(100);
+        let results = store.ingest_path(&root, &embedder, &options).unwrap();
+        assert_eq!(
+            statuses(&results),
+            vec![
+                ("a.md".to_string(), DeepThoughtIngestStatus::Added),
+                ("b.txt".to_string(), DeepThoughtIngestStatus::Added),
+                (
+                    "big.txt".to_string(),
+                    DeepThoughtIngestStatus::Skipped("larger than 100 bytes".to_string())
+                ),
+            ]
+        );
+        let results = store.ingest_path(&root, &embedder, &options).unwrap();
+        assert_eq!(results[0].status, DeepThoughtIngestStatus::Unchanged);
+        assert_eq!(results[1].status, DeepThoughtIngestStatus::Unchanged);
+        assert!(results[1].chunks > 0);
+        std::fs::write(dir.join("docs/b.txt"), "The lazy dog sleeps").unwrap();
+        let results = store.ingest_path(&root, &embedder, &options).unwrap();
+        assert_eq!(results[0].status, DeepThoughtIngestStatus::Unchanged);
+        assert_eq!(results[1].status, DeepThoughtIngestStatus::Updated);
+        let results = store
+            .ingest_path(&root, &embedder, &options.clone().force(true))
+            .unwrap();
+        assert_eq!(results[0].status, DeepThoughtIngestStatus::Updated);
+        assert_eq!(results[1].status, DeepThoughtIngestStatus::Updated);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ingest_path_prunes_excluded_directories() {
+        let dir =
+            std::env::temp_dir().join(format!("deepthought-ingest-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("docs/target")).unwrap();
+        std::fs::write(dir.join("docs/a.txt"), "The quick brown fox").unwrap();
+        // Following this link fails, so entering target/ would report an error
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("docs/target/link")).unwrap();
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let embedder = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let mut store = DeepThoughtVecStore::new(&dir.join("store").display().to_string()).unwrap();
+        let options = DeepThoughtIngestOptions::new()
+            .exclude("target/**")
+            .follow_links(true);
+        let root = dir.join("docs").display().to_string();
+        let results = store.ingest_path(&root, &embedder, &options).unwrap();
+        assert_eq!(
+            statuses(&results),
+            vec![("a.txt".to_string(), DeepThoughtIngestStatus::Added)]
+        );
+        let file = dir.join("docs/a.txt").display().to_string();
+        let options = DeepThoughtIngestOptions::new().exclude("*.txt");
+        let results = store.ingest_path(&file, &embedder, &options).unwrap();
+        assert_eq!(
+            statuses(&results),
+            vec![(
+                "a.txt".to_string(),
+                DeepThoughtIngestStatus::Skipped("excluded by glob patterns".to_string())
+            )]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
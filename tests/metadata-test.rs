@@ -0,0 +1,94 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThought, DeepThoughtBuilder};
+    use std::collections::HashMap;
+
+    const MODEL: &str = "nomic-embed-text-v1.Q5_K_M.gguf";
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog. \
+        The lazy dog sleeps under the old oak tree. \
+        The old oak tree stands on the green hill.";
+
+    fn test_deepthought(name: &str) -> (std::path::PathBuf, DeepThought) {
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-metadata-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let dt = DeepThoughtBuilder::new()
+            .chat_model_gguf(MODEL.to_string())
+            .embed_model_gguf(MODEL.to_string())
+            .dbpath(path.display().to_string())
+            .chunk_size(64)
+            .chunk_overlap(0)
+            .k(20)
+            .max_score(f32::MAX)
+            .build()
+            .unwrap();
+        (path, dt)
+    }
+
+    fn test_metadata() -> HashMap<String, serde_json::Value> {
+        let mut metadata: HashMap<String, serde_json::Value> = HashMap::new();
+        metadata.insert("author".to_string(), serde_json::json!("joe"));
+        metadata.insert("id".to_string(), serde_json::json!("spoof"));
+        metadata.insert("doc_id".to_string(), serde_json::json!("spoof"));
+        metadata.insert("n".to_string(), serde_json::json!(99));
+        metadata.insert("text".to_string(), serde_json::json!("spoof"));
+        metadata
+    }
+
+    #[test]
+    fn test_metadata_on_every_chunk() {
+        let (path, mut dt) = test_deepthought("chunks");
+        let doc_id = dt
+            .add_document_with_metadata(TEXT, &test_metadata())
+            .unwrap();
+        let ids = dt.vecstore.as_ref().unwrap().chunk_ids(&doc_id);
+        assert!(ids.len() > 1);
+        let neighbors = dt.query_vecstore_neighbors("lazy dog").unwrap();
+        assert_eq!(neighbors.len(), ids.len());
+        for neighbor in neighbors.iter() {
+            let n = ids.iter().position(|id| *id == neighbor.id).unwrap();
+            assert_eq!(neighbor.metadata["author"], serde_json::json!("joe"));
+            assert_eq!(neighbor.metadata["id"], serde_json::json!(neighbor.id));
+            assert_eq!(neighbor.metadata["doc_id"], serde_json::json!(doc_id));
+            assert_eq!(neighbor.metadata["n"], serde_json::json!(n));
+            assert_ne!(neighbor.metadata["text"], serde_json::json!("spoof"));
+        }
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_metadata_in_output_template() {
+        let (path, mut dt) = test_deepthought("template");
+        let doc_id = dt
+            .add_document_with_metadata(TEXT, &test_metadata())
+            .unwrap();
+        let vector = dt
+            .embed_model
+            .as_ref()
+            .unwrap()
+            .embed(&["lazy dog"])
+            .unwrap();
+        let store = dt.vecstore.as_mut().unwrap();
+        store
+            .register_template(
+                "cite",
+                "{{ metadata.author }} [{{ metadata.doc_id }}]: {{ text }}",
+            )
+            .unwrap();
+        let neighbors = store
+            .query_neighbors(vector[0].clone(), "lazy dog")
+            .unwrap();
+        assert!(!neighbors.is_empty());
+        for neighbor in neighbors.into_iter() {
+            let rendered = store.output("cite", neighbor).unwrap();
+            assert!(rendered.starts_with(&format!("joe [{}]: ", doc_id)));
+            assert!(!rendered.ends_with("spoof"));
+        }
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
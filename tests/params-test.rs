@@ -0,0 +1,32 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{
+        DeepThoughtContextParams, DeepThoughtKvCacheType, DeepThoughtKvOverride,
+        DeepThoughtModelParams,
+    };
+
+    #[test]
+    fn test_model_params_key() {
+        let params = DeepThoughtModelParams::new().use_mlock(true).kv_override(
+            "tokenizer.ggml.add_bos_token",
+            DeepThoughtKvOverride::Bool(false),
+        );
+        assert_eq!(params.use_mlock, Some(true));
+        assert_eq!(params.use_mmap, None);
+        assert_eq!(params.key(), params.clone().key());
+        assert_ne!(params.key(), DeepThoughtModelParams::default().key());
+    }
+
+    #[test]
+    fn test_context_params_builder() {
+        let params = DeepThoughtContextParams::new()
+            .n_threads(8)
+            .flash_attention(true)
+            .kv_cache_type(DeepThoughtKvCacheType::Q8_0, DeepThoughtKvCacheType::Q8_0);
+        assert_eq!(params.n_threads, Some(8));
+        assert_eq!(params.n_threads_batch, None);
+        assert_eq!(params.type_v, Some(DeepThoughtKvCacheType::Q8_0));
+    }
+}
@@ -0,0 +1,50 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThoughtModelParams, DeepThoughtRouter, DeepThoughtRouterBuilder};
+
+    const MODEL: &str = "nomic-embed-text-v1.Q5_K_M.gguf";
+
+    fn test_router(name: &str) -> (std::path::PathBuf, DeepThoughtRouter) {
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-router-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let router = DeepThoughtRouterBuilder::default()
+            .prompt_model(MODEL)
+            .default_embed_model(MODEL)
+            .catalog_path(&path.join("catalog").display().to_string())
+            .model_params(DeepThoughtModelParams::new().use_mmap(false))
+            .build()
+            .unwrap();
+        (path, router)
+    }
+
+    #[test]
+    fn test_lora_ctx_route_shares_router_weights() {
+        let (path, mut router) = test_router("lora-shared");
+        let loaded = router.loaded_models().unwrap();
+        assert_eq!(loaded.len(), 1);
+        router
+            .new_lora_ctx_route("lora", MODEL, "You are robot!", &[])
+            .unwrap();
+        let loaded = router.loaded_models().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].1, 3);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_lora_ctx_route_missing_adapter() {
+        let (path, mut router) = test_router("lora-missing");
+        assert!(
+            router
+                .new_lora_ctx_route("lora", MODEL, "You are robot!", &[("missing.gguf", 1.0)])
+                .is_err()
+        );
+        assert!(router.get_ctx_route("lora").is_none());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{Mirostat, SamplingParams};
+
+    #[test]
+    fn test_sampling_defaults() {
+        let params = SamplingParams::default();
+        assert_eq!(params.temperature, 0.8);
+        assert_eq!(params.min_p, 0.05);
+        assert_eq!(params.seed, 1337);
+        assert_eq!(params.mirostat, Mirostat::Disabled);
+        assert!(!params.has_penalties());
+    }
+
+    #[test]
+    fn test_sampling_builder() {
+        let params = SamplingParams::new()
+            .temperature(0.2)
+            .top_k(40)
+            .repeat_penalty(1.1)
+            .seed(42);
+        assert_eq!(params.temperature, 0.2);
+        assert_eq!(params.top_k, Some(40));
+        assert_eq!(params.seed, 42);
+        assert!(params.has_penalties());
+        assert_eq!(SamplingParams::deterministic().temperature, 0.0);
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let params = SamplingParams::new().stop("</s>").stop("\nUser:");
+        assert_eq!(params.find_stop(b"hello</s>x"), Some(5));
+        assert_eq!(params.find_stop(b"a\nUser: b </s>"), Some(1));
+        assert_eq!(params.find_stop(b"hello"), None);
+        assert_eq!(params.stop_holdback(b"hello</"), 2);
+        assert_eq!(params.stop_holdback(b"hello\nUs"), 3);
+        assert_eq!(params.stop_holdback(b"hello"), 0);
+    }
+}
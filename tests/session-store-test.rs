@@ -0,0 +1,73 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{
+        DeepThoughtContext, DeepThoughtFileSessionStore, DeepThoughtMessage, DeepThoughtRouter,
+        DeepThoughtSledSessionStore, SessionStore,
+    };
+
+    #[test]
+    fn test_file_session_store() {
+        let path =
+            std::env::temp_dir().join(format!("deepthought-sessions-{}", std::process::id()));
+        let store = DeepThoughtFileSessionStore::new(path.to_str().unwrap()).unwrap();
+        let mut dtc = DeepThoughtContext::init("you are robot").unwrap();
+        dtc.user("hello").unwrap();
+        store.save("user 1/a", &dtc.to_record()).unwrap();
+        assert_eq!(store.names().unwrap(), vec!["user 1/a".to_string()]);
+        let record = store.load("user 1/a").unwrap().unwrap();
+        assert_eq!(record.updated, dtc.updated());
+        assert_eq!(record.messages.len(), 2);
+        assert!(store.load("nobody").unwrap().is_none());
+        assert!(store.delete("user 1/a").unwrap());
+        assert!(!store.delete("user 1/a").unwrap());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_sled_session_store() {
+        let path = std::env::temp_dir().join(format!("deepthought-sled-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let store = DeepThoughtSledSessionStore::new(path.to_str().unwrap()).unwrap();
+        let mut dtc = DeepThoughtContext::init("you are robot").unwrap();
+        dtc.user("hello").unwrap();
+        store.save("user 1/a", &dtc.to_record()).unwrap();
+        store.save("user 2/b", &dtc.to_record()).unwrap();
+        let mut names = store.names().unwrap();
+        names.sort();
+        assert_eq!(names, vec!["user 1/a".to_string(), "user 2/b".to_string()]);
+        let record = store.load("user 1/a").unwrap().unwrap();
+        assert_eq!(record.updated, dtc.updated());
+        assert_eq!(record.messages.len(), 2);
+        assert!(store.load("nobody").unwrap().is_none());
+        assert!(store.delete("user 1/a").unwrap());
+        assert!(!store.delete("user 1/a").unwrap());
+        assert_eq!(store.names().unwrap(), vec!["user 2/b".to_string()]);
+        drop(store);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_in_memory_session_expires() {
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-expired-session-{}.json",
+            std::process::id()
+        ));
+        let mut dtc = DeepThoughtContext::new().unwrap();
+        let mut msg = DeepThoughtMessage::user("hello");
+        msg.timestamp = 1;
+        dtc.push(msg).unwrap();
+        dtc.save(path.to_str().unwrap()).unwrap();
+        let mut router = DeepThoughtRouter::new().unwrap();
+        router
+            .import_session("old", path.to_str().unwrap())
+            .unwrap();
+        router.new_session("fresh").unwrap();
+        router.set_session_ttl(Some(std::time::Duration::from_secs(60)));
+        assert!(router.get_session("old").is_err());
+        assert!(router.get_session("fresh").is_ok());
+        assert_eq!(router.list_sessions(), vec!["fresh".to_string()]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::{DeepThoughtBackend, DeepThoughtMessage, DeepThoughtSession};
+    use std::io::Write;
+
+    fn write_session_file(path: &std::path::Path, header: &[u8], state: &[u8]) {
+        let mut file = std::fs::File::create(path).unwrap();
+        file.write_all(b"DTSESSN1").unwrap();
+        file.write_all(&(header.len() as u64).to_le_bytes())
+            .unwrap();
+        file.write_all(header).unwrap();
+        file.write_all(&(state.len() as u64).to_le_bytes()).unwrap();
+        file.write_all(state).unwrap();
+    }
+
+    #[test]
+    fn test_session_from_other_model_keeps_messages() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let mut model = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let session = DeepThoughtSession {
+            system_prompt: "You are robot!".to_string(),
+            messages: vec![
+                DeepThoughtMessage::system("You are robot!"),
+                DeepThoughtMessage::user("hello"),
+            ],
+            model_path: "other.gguf".to_string(),
+            n_vocab: model.model.n_vocab(),
+            n_ctx_train: model.model.n_ctx_train(),
+            n_embd: model.model.n_embd(),
+            tokens: vec![1, 2, 3],
+        };
+        assert!(!session.matches_model(&model));
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-session-other-{}.session",
+            std::process::id()
+        ));
+        write_session_file(&path, &serde_json::to_vec(&session).unwrap(), &[1u8; 64]);
+        model.load_session(&path.display().to_string()).unwrap();
+        assert_eq!(model.messages.len(), 2);
+        assert!(model.kv_cache.is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_session_block_longer_than_file() {
+        let dtb = DeepThoughtBackend::new().unwrap();
+        let mut model = dtb
+            .load_model("nomic-embed-text-v1.Q5_K_M.gguf", "You are robot!")
+            .unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "deepthought-session-truncated-{}.session",
+            std::process::id()
+        ));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(b"DTSESSN1").unwrap();
+        file.write_all(&u64::MAX.to_le_bytes()).unwrap();
+        file.write_all(b"{}").unwrap();
+        drop(file);
+        assert!(model.load_session(&path.display().to_string()).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_splitter::{
+        fit_token_windows, pack_units, split_sentences, token_windows,
+    };
+    use deepthought::{DeepThoughtLanguage, DeepThoughtSplitter};
+
+    #[test]
+    fn test_token_windows_overlap() {
+        assert_eq!(token_windows(10, 4, 1), vec![(0, 4), (3, 7), (6, 10)]);
+        assert_eq!(token_windows(3, 4, 1), vec![(0, 3)]);
+        assert!(token_windows(0, 4, 1).is_empty());
+    }
+
+    #[test]
+    fn test_fit_token_windows_follow_shrunk_end() {
+        let windows = fit_token_windows(10, 4, 1, |_, end| Ok::<usize, ()>(end - 1)).unwrap();
+        assert_eq!(windows[0], (0, 3));
+        assert_eq!(windows[1], (2, 5));
+        for pair in windows.windows(2) {
+            assert!(pair[1].0 <= pair[0].1);
+        }
+        assert_eq!(windows.last().unwrap().1, 10);
+    }
+
+    #[test]
+    fn test_token_windows_overlap_too_large() {
+        assert_eq!(token_windows(3, 2, 5), vec![(0, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn test_pack_units_overlap() {
+        let units: Vec<String> = ["aaaa", "bbbb", "cccc", "dddd"]
+            .iter()
+            .map(|unit| unit.to_string())
+            .collect();
+        let packed = pack_units(&units, " ", 9, 5);
+        assert_eq!(packed[0], ("aaaa bbbb".to_string(), 0, 1));
+        assert_eq!(packed[1], ("bbbb cccc".to_string(), 1, 2));
+        assert_eq!(packed[2], ("cccc dddd".to_string(), 2, 3));
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        let sentences = split_sentences("One is 1.5 units. Two! Three?\n\nFour");
+        assert_eq!(
+            sentences,
+            vec!["One is 1.5 units.", "Two!", "Three?", "Four"]
+        );
+    }
+
+    #[test]
+    fn test_markdown_heading_path() {
+        let text =
+            "# Guide\nIntro.\n## Install\nRun it.\n```\n# not a heading\n```\n# Other\nMore.";
+        let chunks = DeepThoughtSplitter::Markdown.split(text, 1024, 0);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].metadata["heading_path"], "Guide > Install");
+        assert!(chunks[1].text.contains("# not a heading"));
+        assert_eq!(chunks[2].metadata["heading_path"], "Other");
+    }
+
+    #[test]
+    fn test_code_boundaries() {
+        let text = "use std::io;\n\n/// First\nfn first() {}\n\n#[inline]\npub fn second() {}\n";
+        let chunks = DeepThoughtSplitter::Code(DeepThoughtLanguage::Rust).split(text, 30, 0);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].metadata["symbol"], "fn first() {}");
+        assert_eq!(chunks[1].metadata["line_start"], 3);
+        assert!(chunks[2].text.starts_with("#[inline]"));
+        assert_eq!(chunks[2].metadata["language"], "rust");
+    }
+
+    #[test]
+    fn test_lines_ranges() {
+        let chunks = DeepThoughtSplitter::Lines.split("a1\nb2\nc3\nd4", 5, 0);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].text, "c3\nd4");
+        assert_eq!(chunks[1].metadata["line_start"], 3);
+        assert_eq!(chunks[1].metadata["line_end"], 4);
+    }
+}
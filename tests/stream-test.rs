@@ -0,0 +1,27 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::DeepThoughtStream;
+    use std::io::Write;
+
+    #[test]
+    fn test_stream_multibyte_across_writes() {
+        let mut pieces: Vec<String> = Vec::new();
+        let mut stream = DeepThoughtStream::new(|piece: &str| pieces.push(piece.to_string()));
+        for b in "héllo 世界".as_bytes() {
+            stream.write_all(&[*b]).unwrap();
+        }
+        let text = stream.finish();
+        assert_eq!(text, "héllo 世界");
+        assert!(pieces.contains(&"世".to_string()));
+    }
+
+    #[test]
+    fn test_stream_invalid_bytes() {
+        let mut stream = DeepThoughtStream::new(|_: &str| {});
+        stream.write_all(&[0xff, b'a', 0xe4]).unwrap();
+        assert_eq!(stream.text(), "\u{FFFD}a");
+        assert_eq!(stream.finish(), "\u{FFFD}a\u{FFFD}");
+    }
+}
@@ -0,0 +1,21 @@
+#[cfg(test)]
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+    use deepthought::deepthought_tokenizer::valid_utf8_prefix;
+
+    #[test]
+    fn test_valid_utf8_prefix_drops_split_character() {
+        let euro = "€".as_bytes();
+        let pieces = vec![
+            b"price ".to_vec(),
+            euro[..2].to_vec(),
+            euro[2..].to_vec(),
+            b" 5".to_vec(),
+        ];
+        assert_eq!(valid_utf8_prefix(&pieces), "price € 5");
+        assert_eq!(valid_utf8_prefix(&pieces[..3]), "price €");
+        assert_eq!(valid_utf8_prefix(&pieces[..2]), "price ");
+        assert_eq!(valid_utf8_prefix(&[]), "");
+    }
+}